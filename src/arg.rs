@@ -1,16 +1,70 @@
 use std::{path::PathBuf, sync::Arc};
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use color_eyre::eyre::Result;
 use tokio::sync::OnceCell;
 
+use crate::{
+    platforms::{
+        media::MediaOptions, sink::SinkKind, structs::AttachmentKind, telegram::structs::DialogKind,
+    },
+    utils::parse_byte_size,
+};
+
 //* TYPEDEF */
+#[derive(Debug, Clone, Args)]
+pub struct MediaArguments {
+    /// Download message attachments into a content addressed store, listed in its manifest.jsonl.
+    #[arg(long, default_value_t = false)]
+    pub download_media: bool,
+
+    /// Directory attachments are stored in, created when missing.
+    #[arg(long, value_name = "DIRECTORY", default_value = "media")]
+    pub media_dir: PathBuf,
+
+    /// Skip attachments larger than this, e.g. 500K or 20M.
+    #[arg(long, value_name = "SIZE", value_parser = parse_byte_size)]
+    pub media_max_size: Option<u64>,
+
+    /// Only download these kinds, comma separated: image, video, audio, voice, sticker, document, other.
+    #[arg(long, value_name = "KINDS", value_delimiter = ',')]
+    pub media_kinds: Vec<AttachmentKind>,
+
+    /// Stop downloading once this much has been stored over the whole run, e.g. 2G.
+    #[arg(long, value_name = "SIZE", value_parser = parse_byte_size)]
+    pub media_budget: Option<u64>,
+}
+
+impl MediaArguments {
+    /// The store options, when downloading was asked for.
+    pub fn options(&self) -> Option<MediaOptions> {
+        self.download_media.then(|| {
+            MediaOptions::new(
+                self.media_dir.clone(),
+                self.media_max_size,
+                self.media_kinds.clone(),
+                self.media_budget,
+            )
+        })
+    }
+}
+
 #[derive(Debug, Subcommand)]
 pub enum TelegramCommands {
+    /// Log in once and save the session for later runs.
+    Login {
+        /// Sign in as a bot, the token is read from TELEGRAM_BOT_TOKEN or prompted for.
+        #[arg(long, default_value_t = false)]
+        bot: bool,
+    },
     ResolveUsers {
-        /// .txt file containing usernames, separated by newlines
+        /// .txt file with a target per line: a username or @name, a t.me link, a t.me/c post link, an invite link or the id of a chat in the session's dialogs
         #[arg(short, long, value_name = "TEXT_FILE")]
         usernames: PathBuf,
+
+        /// Exit with an error when any target failed, after the failure report is written.
+        #[arg(long, default_value_t = false)]
+        fail_on_error: bool,
     },
     DumpMessages {
         /// .json file containing resolved usernames, outputted by the resolve-users command
@@ -21,13 +75,77 @@ pub enum TelegramCommands {
         #[arg(short, long)]
         limit: Option<u32>,
 
-        /// Unix timestamp to serve as a start point, default behaviour is to get the most recent messages starting from this date.
-        #[arg(short, long)]
+        /// Unix timestamp bounding the dump, default behaviour is to get the most recent messages down to this date.
+        #[arg(short, long, visible_alias = "since")]
         date_point: i64,
 
-        /// Reverse default behaviour. If date_point is set whilst this is true, then all messages before the date_point will be retrieved. Ignored when limit is set
+        /// Unix timestamp of the newest message to include. Iteration seeks straight to it instead of starting from the most recent message.
+        #[arg(long)]
+        until: Option<i64>,
+
+        /// Reverse default behaviour. Messages are retrieved oldest first, walking forward from date_point to the present.
         #[arg(short, long, default_value_t = false)]
         reverse: bool,
+
+        /// Checkpoint every target as it is dumped, and continue from the checkpoints an interrupted --resume run over the same window left behind.
+        #[arg(long, default_value_t = false)]
+        resume: bool,
+
+        /// Where dumped messages are written.
+        #[arg(long, default_value = "json")]
+        sink: SinkKind,
+
+        /// Exit with an error when any target failed, after the failure report is written.
+        #[arg(long, default_value_t = false)]
+        fail_on_error: bool,
+
+        #[command(flatten)]
+        media: MediaArguments,
+    },
+    /// List the dialogs the session can access.
+    ListDialogs {
+        /// Only these kinds, comma separated: channel, supergroup, group, private.
+        #[arg(short, long, value_name = "KINDS", value_delimiter = ',')]
+        kinds: Vec<DialogKind>,
+
+        /// Only dialogs whose title or username contains this, ignoring case.
+        #[arg(short, long)]
+        name: Option<String>,
+
+        /// Only dialogs with (true) or without (false) unread messages.
+        #[arg(long)]
+        unread: Option<bool>,
+
+        /// Only archived (true) or unarchived (false) dialogs.
+        #[arg(long)]
+        archived: Option<bool>,
+
+        /// Write the dialogs to this .json file as resolved peers, ready for dump-messages, instead of printing a table.
+        #[arg(short, long, value_name = "JSON_FILE")]
+        output: Option<PathBuf>,
+    },
+    /// Fetch only the messages newer than those already stored for each peer, appending them to the store.
+    Sync {
+        /// .json file containing resolved usernames, outputted by the resolve-users command
+        #[arg(short, long, value_name = "JSON_FILE")]
+        users_file: PathBuf,
+
+        /// Unix timestamp to start from for peers that have nothing stored yet, only needed when some don't.
+        #[arg(short, long)]
+        date_point: Option<i64>,
+
+        /// Directory holding the per-peer message stores, created when missing.
+        #[arg(short, long, value_name = "DIRECTORY", default_value = "store")]
+        store: PathBuf,
+
+        /// Earlier dump to start peers with nothing stored yet from, right after their newest message in it.
+        /// Only its position is taken, the messages it holds are not copied into the store. Repeatable.
+        #[arg(long, value_name = "DUMP_FILE")]
+        seed: Vec<PathBuf>,
+
+        /// Exit with an error when any target failed, after the failure report is written.
+        #[arg(long, default_value_t = false)]
+        fail_on_error: bool,
     },
 }
 
@@ -42,13 +160,139 @@ pub enum DiscordCommands {
         #[arg(short, long)]
         limit: Option<u32>,
 
-        /// Unix timestamp to serve as a start point, default behaviour is to get the most recent messages starting from this date.
+        /// Unix timestamp bounding the dump, default behaviour is to get the most recent messages down to this date.
+        #[arg(short, long)]
+        date_point: i64,
+
+        /// Reverse default behaviour. Messages are retrieved oldest first, walking forward from date_point to the present.
+        #[arg(short, long, default_value_t = false)]
+        reverse: bool,
+
+        /// Checkpoint every target as it is dumped, and continue from the checkpoints an interrupted --resume run over the same window left behind.
+        #[arg(long, default_value_t = false)]
+        resume: bool,
+
+        /// Where dumped messages are written.
+        #[arg(long, default_value = "json")]
+        sink: SinkKind,
+
+        /// Exit with an error when any target failed, after the failure report is written.
+        #[arg(long, default_value_t = false)]
+        fail_on_error: bool,
+
+        #[command(flatten)]
+        media: MediaArguments,
+    },
+    /// Dump every text and announcement channel and active thread of a guild, tagging each with the guild and channel names.
+    DumpGuild {
+        /// Guild id
+        #[arg(short, long)]
+        guild: String,
+
+        /// Arbitrary limit to number of messages to be dumped per channel starting from date_point in either direction.
+        #[arg(short, long)]
+        limit: Option<u32>,
+
+        /// Unix timestamp bounding the dump, default behaviour is to get the most recent messages down to this date.
+        #[arg(short, long)]
+        date_point: i64,
+
+        /// Reverse default behaviour. Messages are retrieved oldest first, walking forward from date_point to the present.
+        #[arg(short, long, default_value_t = false)]
+        reverse: bool,
+
+        /// Checkpoint every target as it is dumped, and continue from the checkpoints an interrupted --resume run over the same window left behind.
+        #[arg(long, default_value_t = false)]
+        resume: bool,
+
+        /// Where dumped messages are written.
+        #[arg(long, default_value = "json")]
+        sink: SinkKind,
+
+        /// Exit with an error when any target failed, after the failure report is written.
+        #[arg(long, default_value_t = false)]
+        fail_on_error: bool,
+
+        #[command(flatten)]
+        media: MediaArguments,
+    },
+    /// Dump the active and archived threads of a channel, or the posts of a forum, linking each to the channel and its starter message.
+    DumpThreads {
+        /// Id of the channel or forum the threads were opened in
+        #[arg(short, long)]
+        channel: String,
+
+        /// Also list archived private threads, needs the Manage Threads permission and is skipped without it.
+        #[arg(long, default_value_t = false)]
+        private: bool,
+
+        /// Arbitrary limit to number of messages to be dumped per thread starting from date_point in either direction.
+        #[arg(short, long)]
+        limit: Option<u32>,
+
+        /// Unix timestamp bounding the dump, default behaviour is to get the most recent messages down to this date.
         #[arg(short, long)]
         date_point: i64,
 
-        /// Reverse default behaviour. If date_point is set whilst this is true, then all messages before the date_point will be retrieved. Ignored when limit is set
+        /// Reverse default behaviour. Messages are retrieved oldest first, walking forward from date_point to the present.
         #[arg(short, long, default_value_t = false)]
         reverse: bool,
+
+        /// Checkpoint every target as it is dumped, and continue from the checkpoints an interrupted --resume run over the same window left behind.
+        #[arg(long, default_value_t = false)]
+        resume: bool,
+
+        /// Where dumped messages are written.
+        #[arg(long, default_value = "json")]
+        sink: SinkKind,
+
+        /// Exit with an error when any target failed, after the failure report is written.
+        #[arg(long, default_value_t = false)]
+        fail_on_error: bool,
+
+        #[command(flatten)]
+        media: MediaArguments,
+    },
+    /// List the guilds the account is a member of.
+    ListGuilds,
+    /// List the channels of a guild.
+    ListChannels {
+        /// Guild id
+        #[arg(short, long)]
+        guild: String,
+
+        /// Write the ids of the channels holding messages to this .txt file, ready for dump-messages, instead of printing a table.
+        #[arg(short, long, value_name = "TEXT_FILE")]
+        output: Option<PathBuf>,
+    },
+    /// List open direct messages and group DMs.
+    ListDms {
+        /// Write the channel ids to this .txt file, ready for dump-messages, instead of printing a table.
+        #[arg(short, long, value_name = "TEXT_FILE")]
+        output: Option<PathBuf>,
+    },
+    /// Fetch only the messages newer than those already stored for each channel, appending them to the store.
+    Sync {
+        /// .txt file containing channel ids
+        #[arg(short, long, value_name = "TEXT_FILE")]
+        targets_file: PathBuf,
+
+        /// Unix timestamp to start from for channels that have nothing stored yet, only needed when some don't.
+        #[arg(short, long)]
+        date_point: Option<i64>,
+
+        /// Directory holding the per-channel message stores, created when missing.
+        #[arg(short, long, value_name = "DIRECTORY", default_value = "store")]
+        store: PathBuf,
+
+        /// Earlier dump to start channels with nothing stored yet from, right after their newest message in it.
+        /// Only its position is taken, the messages it holds are not copied into the store. Repeatable.
+        #[arg(long, value_name = "DUMP_FILE")]
+        seed: Vec<PathBuf>,
+
+        /// Exit with an error when any target failed, after the failure report is written.
+        #[arg(long, default_value_t = false)]
+        fail_on_error: bool,
     },
 }
 
@@ -64,12 +308,66 @@ pub enum PlatformCommands {
     },
 }
 
+#[derive(Debug, Subcommand)]
+pub enum ExportCommands {
+    /// Flatten dumps into a single Parquet file with a row per message
+    Parquet {
+        /// Dump files written by dump-messages, either .json or .jsonl
+        #[arg(short, long, value_name = "DUMP_FILE", num_args = 1.., required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Parquet file to write
+        #[arg(short, long, value_name = "PARQUET_FILE")]
+        output: PathBuf,
+
+        /// Platform recorded on every row, taken from each dump's metadata (or filename, for older dumps) when omitted.
+        #[arg(short, long)]
+        platform: Option<String>,
+    },
+    /// Flatten dumps into a CSV file with a row per message. Cells that a spreadsheet would evaluate as a formula are escaped
+    Csv {
+        /// Dump files written by dump-messages, either .json or .jsonl
+        #[arg(short, long, value_name = "DUMP_FILE", num_args = 1.., required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// CSV file to write
+        #[arg(short, long, value_name = "CSV_FILE")]
+        output: PathBuf,
+
+        /// Platform recorded on every row, taken from each dump's metadata (or filename, for older dumps) when omitted.
+        #[arg(short, long)]
+        platform: Option<String>,
+
+        /// Separate columns with tabs instead of commas.
+        #[arg(long, default_value_t = false)]
+        tsv: bool,
+
+        /// Start the file with a UTF-8 byte order mark, so Excel opens Hebrew text with the right encoding.
+        #[arg(long, default_value_t = false)]
+        bom: bool,
+    },
+}
+
 #[derive(Debug, Subcommand)]
 pub enum ApplicationCommands {
     Data {
         #[command(subcommand)]
         platform: PlatformCommands,
     },
+    Export {
+        #[command(subcommand)]
+        format: ExportCommands,
+    },
+    /// Upgrade dumps written by older versions to the current dump format
+    Migrate {
+        /// Dump files to upgrade, either .json or .jsonl
+        #[arg(short, long, value_name = "DUMP_FILE", num_args = 1.., required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Overwrite each dump instead of writing the upgraded copy next to it as <name>_v<version>.
+        #[arg(long, default_value_t = false)]
+        in_place: bool,
+    },
 }
 
 #[derive(Debug, Parser)]
@@ -77,9 +375,13 @@ pub enum ApplicationCommands {
 pub struct ApplicationArguments {
     #[command(subcommand)]
     pub command: ApplicationCommands,
+
+    /// Fail instead of prompting when the Telegram session is not logged in, for unattended runs.
+    #[arg(long, global = true, default_value_t = false)]
+    pub no_prompt: bool,
 }
 //* END TYPEDEF */
-pub const APPLICATION_ARGUMENTS: OnceCell<Arc<ApplicationArguments>> = OnceCell::const_new();
+pub static APPLICATION_ARGUMENTS: OnceCell<Arc<ApplicationArguments>> = OnceCell::const_new();
 
 fn init_application_argument() -> Result<Arc<ApplicationArguments>> {
     Ok(Arc::new(ApplicationArguments::try_parse()?))
@@ -8,6 +8,8 @@ pub struct TelegramEnvironment {
     pub api_id: u32,
     pub api_hash: String,
     pub session_path: String,
+    /// Signs the session in as a bot when it is not logged in yet, instead of prompting for a phone
+    pub bot_token: Option<String>,
 }
 
 impl Display for TelegramEnvironment {
@@ -20,17 +22,25 @@ impl Display for TelegramEnvironment {
     }
 }
 
+pub const DEFAULT_DISCORD_API_BASE: &str = "https://discord.com/api/v10";
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DiscordEnvironment {
     pub auth_file: String,
+    /// REST API root, overridable to point the client at a mock server
+    pub api_base: String,
+    /// Proxy every Discord request goes through, e.g. `socks5://127.0.0.1:9050`
+    pub proxy: Option<String>,
 }
 
 impl Display for DiscordEnvironment {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "[-- DISCORD ENVIRONMENT --]\nAUTH_FILE: {}\n",
-            self.auth_file
+            "[-- DISCORD ENVIRONMENT --]\nAUTH_FILE: {}\nAPI_BASE: {}\nPROXY: {}\n",
+            self.auth_file,
+            self.api_base,
+            self.proxy.as_deref().unwrap_or("NONE")
         )
     }
 }
@@ -48,9 +58,13 @@ impl Environment {
                 api_id: env::var("API_ID")?.parse::<u32>()?,
                 api_hash: env::var("API_HASH")?,
                 session_path: env::var("SESSION_PATH")?,
+                bot_token: env::var("TELEGRAM_BOT_TOKEN").ok(),
             },
             discord: DiscordEnvironment {
                 auth_file: env::var("AUTH_FILE")?,
+                api_base: env::var("DISCORD_API_BASE")
+                    .unwrap_or_else(|_| DEFAULT_DISCORD_API_BASE.to_string()),
+                proxy: env::var("DISCORD_PROXY").ok(),
             },
         })
     }
@@ -9,7 +9,7 @@ pub struct DecodingError {
 
 impl DecodingError {
     pub fn new(filepath: String) -> Self {
-        Self { filepath: filepath }
+        Self { filepath }
     }
 }
 
@@ -33,8 +33,8 @@ pub struct DeserializationError {
 impl DeserializationError {
     pub fn new(filepath: String, error_object: String) -> Self {
         Self {
-            filepath: filepath,
-            error_object: error_object,
+            filepath,
+            error_object,
         }
     }
 }
@@ -62,10 +62,7 @@ pub struct UsernameResolveError {
 
 impl UsernameResolveError {
     pub fn new(username: String, message: String) -> Self {
-        Self {
-            username: username,
-            message: message,
-        }
+        Self { username, message }
     }
 }
 
@@ -88,10 +85,7 @@ pub struct FileExtensionError {
 
 impl FileExtensionError {
     pub fn new(expected: String, found: String) -> Self {
-        Self {
-            expected: expected,
-            found: found,
-        }
+        Self { expected, found }
     }
 }
 
@@ -117,9 +111,7 @@ pub struct FileNotFoundError {
 
 impl FileNotFoundError {
     pub fn new(expected_path: String) -> Self {
-        Self {
-            expected_path: expected_path,
-        }
+        Self { expected_path }
     }
 }
 
@@ -143,8 +135,8 @@ pub struct DateTimeParseError {
 impl DateTimeParseError {
     pub fn new(datestring: String, error_object: String) -> Self {
         Self {
-            datestring: datestring,
-            error_object: error_object,
+            datestring,
+            error_object,
         }
     }
 }
@@ -171,7 +163,7 @@ pub struct DiscordClientInitializationError {
 
 impl DiscordClientInitializationError {
     pub fn new(message: String) -> Self {
-        Self { message: message }
+        Self { message }
     }
 }
 
@@ -193,7 +185,7 @@ pub struct InvalidIDError {
 
 impl InvalidIDError {
     pub fn new(ids: Vec<String>) -> Self {
-        Self { ids: ids }
+        Self { ids }
     }
 }
 
@@ -207,9 +199,308 @@ impl Display for InvalidIDError {
         )?;
 
         for id in self.ids.iter() {
-            write!(f, "   {}\n", id.red())?;
+            writeln!(f, "   {}", id.red())?;
         }
 
         write!(f, "{}", "]".red())
     }
 }
+
+#[derive(Debug)]
+pub struct DiscordResponseError {
+    pub url: String,
+    pub status: u16,
+    pub body: String,
+}
+
+impl DiscordResponseError {
+    pub fn new(url: String, status: u16, body: String) -> Self {
+        Self { url, status, body }
+    }
+}
+
+impl Display for DiscordResponseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\n{}",
+            "[DISCORD_RESPONSE_ERROR]".black().on_red(),
+            format!(
+                "Discord answered {} with status {}: {}",
+                self.url, self.status, self.body
+            )
+            .red()
+        )
+    }
+}
+
+/// Discord's JSON error codes this tool tells apart, see the JSON error codes table in Discord's API docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiscordAPIErrorKind {
+    UnknownChannel,
+    UnknownGuild,
+    UnknownMessage,
+    MissingAccess,
+    MissingPermissions,
+    Unauthorized,
+    InvalidToken,
+    Other,
+}
+
+impl DiscordAPIErrorKind {
+    pub fn from_response(status: u16, code: u32) -> Self {
+        match code {
+            10003 => DiscordAPIErrorKind::UnknownChannel,
+            10004 => DiscordAPIErrorKind::UnknownGuild,
+            10008 => DiscordAPIErrorKind::UnknownMessage,
+            50001 => DiscordAPIErrorKind::MissingAccess,
+            50013 => DiscordAPIErrorKind::MissingPermissions,
+            50014 => DiscordAPIErrorKind::InvalidToken,
+            _ if status == 401 => DiscordAPIErrorKind::Unauthorized,
+            _ => DiscordAPIErrorKind::Other,
+        }
+    }
+
+    /// Failures that concern a single target, the rest of a batch can go on without it.
+    /// An unknown message is not one of them, no request made for a whole target names a message.
+    pub fn is_target_specific(&self) -> bool {
+        matches!(
+            self,
+            DiscordAPIErrorKind::UnknownChannel
+                | DiscordAPIErrorKind::UnknownGuild
+                | DiscordAPIErrorKind::MissingAccess
+                | DiscordAPIErrorKind::MissingPermissions
+        )
+    }
+
+    fn reason(&self) -> &'static str {
+        match self {
+            DiscordAPIErrorKind::UnknownChannel => "no channel with this id, check the id",
+            DiscordAPIErrorKind::UnknownGuild => "no guild with this id, check the id",
+            DiscordAPIErrorKind::UnknownMessage => "no message with this id",
+            DiscordAPIErrorKind::MissingAccess => "the account cannot see this channel",
+            DiscordAPIErrorKind::MissingPermissions => {
+                "the account lacks the permission to read this channel"
+            }
+            DiscordAPIErrorKind::Unauthorized => {
+                "the credentials were rejected, refresh the auth file"
+            }
+            DiscordAPIErrorKind::InvalidToken => {
+                "the authorization token is invalid, refresh the auth file"
+            }
+            DiscordAPIErrorKind::Other => "unexpected error",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DiscordAPIError {
+    pub resource: String,
+    pub status: u16,
+    pub code: u32,
+    pub message: String,
+    pub kind: DiscordAPIErrorKind,
+}
+
+impl DiscordAPIError {
+    pub fn new(resource: String, status: u16, code: u32, message: String) -> Self {
+        Self {
+            resource,
+            status,
+            code,
+            message,
+            kind: DiscordAPIErrorKind::from_response(status, code),
+        }
+    }
+
+    /// One line explanation, used where a failed target is reported next to the ones that went through.
+    pub fn reason(&self) -> String {
+        format!(
+            "{} ({}, code {})",
+            self.kind.reason(),
+            self.message,
+            self.code
+        )
+    }
+}
+
+impl Display for DiscordAPIError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\n{}",
+            "[DISCORD_API_ERROR]".black().on_red(),
+            format!(
+                "Discord refused {} with status {}: {}",
+                self.resource,
+                self.status,
+                self.reason()
+            )
+            .red()
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct MissingAuthorizationError {
+    pub filepath: String,
+    pub format: String,
+}
+
+impl MissingAuthorizationError {
+    pub fn new(filepath: String, format: String) -> Self {
+        Self { filepath, format }
+    }
+}
+
+impl Display for MissingAuthorizationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\n{}",
+            "[MISSING_AUTHORIZATION_ERROR]".black().on_red(),
+            format!(
+                "No Authorization header found in '{}' (read as {}). Copy a request to discord.com/api made while logged in, or put the token alone in the file",
+                self.filepath, self.format
+            )
+            .red()
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct BatchFailureError {
+    pub failed: usize,
+    pub total: usize,
+    pub report: String,
+}
+
+impl BatchFailureError {
+    pub fn new(failed: usize, total: usize, report: String) -> Self {
+        Self {
+            failed,
+            total,
+            report,
+        }
+    }
+}
+
+impl Display for BatchFailureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\n{}",
+            "[BATCH_FAILURE_ERROR]".black().on_red(),
+            format!(
+                "{} of {} targets failed, reasons are listed in {}",
+                self.failed, self.total, self.report
+            )
+            .red()
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct TelegramAuthorizationError {
+    pub session_path: String,
+    pub message: String,
+}
+
+impl TelegramAuthorizationError {
+    pub fn new(session_path: String, message: String) -> Self {
+        Self {
+            session_path,
+            message,
+        }
+    }
+}
+
+impl Display for TelegramAuthorizationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\n{}",
+            "[TELEGRAM_AUTHORIZATION_ERROR]".black().on_red(),
+            format!("Session '{}': {}", self.session_path, self.message).red()
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct CheckpointMismatchError {
+    pub target: String,
+    pub filepath: String,
+}
+
+impl CheckpointMismatchError {
+    pub fn new(target: String, filepath: String) -> Self {
+        Self { target, filepath }
+    }
+}
+
+impl Display for CheckpointMismatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\n{}",
+            "[CHECKPOINT_MISMATCH_ERROR]".black().on_red(),
+            format!(
+                "Checkpoint '{}' of {} was taken with another date point, until, limit or direction, run without --resume to start it over",
+                self.filepath, self.target
+            )
+            .red()
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct SyncStartError {
+    pub target: String,
+}
+
+impl SyncStartError {
+    pub fn new(target: String) -> Self {
+        Self { target }
+    }
+}
+
+impl Display for SyncStartError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\n{}",
+            "[SYNC_START_ERROR]".black().on_red(),
+            format!(
+                "Nothing is stored for {} yet, give --date-point or a --seed dump holding it to start from",
+                self.target
+            )
+            .red()
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct StalePeerError {
+    pub target: String,
+}
+
+impl StalePeerError {
+    pub fn new(target: String) -> Self {
+        Self { target }
+    }
+}
+
+impl Display for StalePeerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\n{}",
+            "[STALE_PEER_ERROR]".black().on_red(),
+            format!(
+                "Telegram no longer accepts the stored access hash of {}, which has no public username and is not among the session's dialogs, run resolve-users for it again",
+                self.target
+            )
+            .red()
+        )
+    }
+}
@@ -0,0 +1,346 @@
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Result, eyre};
+use csv::{Terminator, WriterBuilder};
+use indicatif::ProgressBar;
+use serde_json::{Value, from_slice};
+use tokio::{
+    fs::{metadata, read},
+    task::spawn_blocking,
+};
+
+use crate::{
+    error::DeserializationError,
+    platforms::{
+        columnar::ParquetStore,
+        envelope::{
+            DUMP_FORMAT_VERSION, DumpEnvelope, DumpMetadata, document_version, upgrade_document,
+        },
+        sink::JsonLinesRecord,
+        structs::DumpedPeer,
+    },
+    utils::read_json_lines,
+    visual::new_multi_progress,
+};
+
+/// Messages handed to a columnar writer at once, keeps batches bounded for very large peers.
+const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Fallback metadata for dumps written before they carried any, dated by the file's modification time.
+async fn legacy_metadata(filepath: &PathBuf) -> Result<DumpMetadata> {
+    let modified = metadata(filepath).await?.modified()?;
+
+    Ok(DumpMetadata::legacy(
+        infer_platform(filepath),
+        DateTime::<Utc>::from(modified),
+    ))
+}
+
+/// Reads any dump written by dump-messages, JSON documents and JSON lines files alike.
+/// Dumps in an older format are upgraded in memory.
+pub async fn read_dump_file(filepath: &PathBuf) -> Result<DumpEnvelope> {
+    Ok(read_versioned_dump_file(filepath).await?.1)
+}
+
+/// Same as `read_dump_file`, along with the format version the file was written in.
+pub async fn read_versioned_dump_file(filepath: &PathBuf) -> Result<(u32, DumpEnvelope)> {
+    let is_json_lines = filepath
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("jsonl"))
+        .unwrap_or(false);
+
+    let legacy = legacy_metadata(filepath).await?;
+
+    let deserialization_error = |reason: String| {
+        eyre!(DeserializationError::new(
+            filepath.to_string_lossy().to_string(),
+            reason
+        ))
+    };
+
+    if is_json_lines {
+        let mut records = read_json_lines::<JsonLinesRecord>(filepath)
+            .await?
+            .into_iter()
+            .peekable();
+
+        let (version, dump_metadata) = match records.peek() {
+            Some(JsonLinesRecord::Metadata(dump_metadata)) => {
+                if dump_metadata.format_version > DUMP_FORMAT_VERSION {
+                    return Err(deserialization_error(format!(
+                        "format version {} is newer than the supported version {}, update the tool",
+                        dump_metadata.format_version, DUMP_FORMAT_VERSION
+                    )));
+                }
+                let dump_metadata = dump_metadata.clone();
+                records.next();
+                (dump_metadata.format_version, dump_metadata)
+            }
+            _ => (1, legacy),
+        };
+
+        let mut peers: Vec<DumpedPeer> = vec![];
+
+        for record in records {
+            match record {
+                JsonLinesRecord::Metadata(_) => {
+                    return Err(deserialization_error(
+                        "metadata record found past the first line".to_string(),
+                    ));
+                }
+                JsonLinesRecord::Header(header) => {
+                    peers.push(DumpedPeer::new(header.peer, vec![]));
+                }
+                JsonLinesRecord::Message(message) => match peers.last_mut() {
+                    Some(peer) => peer.chunks.push(message),
+                    None => {
+                        return Err(deserialization_error(
+                            "message record found before any peer header".to_string(),
+                        ));
+                    }
+                },
+            }
+        }
+
+        return Ok((version, DumpEnvelope::new(dump_metadata, peers)));
+    }
+
+    let buf = read(filepath).await?;
+    let document = from_slice::<Value>(&buf).map_err(|e| deserialization_error(e.to_string()))?;
+    let version = document_version(&document);
+
+    Ok((
+        version,
+        upgrade_document(document, || legacy).map_err(deserialization_error)?,
+    ))
+}
+
+/// Dumps are named after the platform they came from, e.g. telegram_<username>_<time>.json.
+fn infer_platform(filepath: &Path) -> String {
+    let name = filepath
+        .file_name()
+        .map(|n| n.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    ["telegram", "discord"]
+        .iter()
+        .find(|platform| name.starts_with(&format!("{}_", platform)))
+        .map(|platform| platform.to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+pub async fn export_parquet(
+    inputs: Vec<PathBuf>,
+    output: PathBuf,
+    platform: Option<String>,
+) -> Result<()> {
+    let (multiprog, style) = new_multi_progress()?;
+
+    let store = ParquetStore::create(output, "unknown").await?;
+
+    for input in inputs.iter() {
+        let DumpEnvelope { metadata, peers } = read_dump_file(input).await?;
+        let store = store.for_platform(&platform.clone().unwrap_or(metadata.platform));
+
+        let progress = multiprog.add(ProgressBar::new(
+            peers.iter().map(|p| p.chunks.len() as u64).sum(),
+        ));
+        progress.set_style(style.clone());
+        progress.set_message(input.to_string_lossy().to_string());
+
+        for peer in peers.into_iter() {
+            for chunk in peer.chunks.chunks(EXPORT_CHUNK_SIZE) {
+                store.write(&peer.peer, chunk.to_vec()).await?;
+                progress.inc(chunk.len() as u64);
+            }
+        }
+
+        progress.finish_with_message(format!("{} - Exported", input.to_string_lossy()));
+    }
+
+    store.close().await?;
+
+    Ok(())
+}
+
+/// Spreadsheets evaluate cells starting with one of these as formulas.
+const FORMULA_TRIGGERS: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+/// A sign followed by digits with at most one decimal point, e.g. `-1001234567890` or `+3.5`.
+/// Unlike `f64` parsing, `-inf`, `-NaN` and exponents don't count.
+fn is_plain_numeral(cell: &str) -> bool {
+    let digits = cell.strip_prefix(['+', '-']).unwrap_or(cell);
+
+    digits.chars().any(|c| c.is_ascii_digit())
+        && digits.chars().all(|c| c.is_ascii_digit() || c == '.')
+        && digits.matches('.').count() <= 1usize
+}
+
+/// Prefixes a quote to cells a spreadsheet would run as a formula. Plain numbers such as negative
+/// Telegram chat ids are left alone, they are not evaluated.
+fn neutralize_formula(cell: &str) -> Cow<'_, str> {
+    match cell.chars().next() {
+        Some(first) if FORMULA_TRIGGERS.contains(&first) && !is_plain_numeral(cell) => {
+            Cow::Owned(format!("'{}", cell))
+        }
+        _ => Cow::Borrowed(cell),
+    }
+}
+
+pub async fn export_csv(
+    inputs: Vec<PathBuf>,
+    output: PathBuf,
+    platform: Option<String>,
+    delimiter: u8,
+    bom: bool,
+) -> Result<()> {
+    let (multiprog, style) = new_multi_progress()?;
+
+    let mut file = File::create(&output)?;
+    if bom {
+        file.write_all("\u{FEFF}".as_bytes())?;
+    }
+
+    // Multi-line message bodies are quoted as needed, CRLF rows keep Excel happy
+    let mut writer = WriterBuilder::new()
+        .delimiter(delimiter)
+        .terminator(Terminator::CRLF)
+        .from_writer(file);
+
+    writer.write_record([
+        "peer_id",
+        "peer_username",
+        "platform",
+        "id",
+        "user_id",
+        "message",
+        "date",
+        "reply_to",
+        "edited_at",
+        "forwarded_from",
+        "attachments",
+        "reactions",
+    ])?;
+
+    for input in inputs.iter() {
+        let DumpEnvelope { metadata, peers } = read_dump_file(input).await?;
+        let platform = platform.clone().unwrap_or(metadata.platform);
+
+        let progress = multiprog.add(ProgressBar::new(
+            peers.iter().map(|p| p.chunks.len() as u64).sum(),
+        ));
+        progress.set_style(style.clone());
+        progress.set_message(input.to_string_lossy().to_string());
+
+        let input_name = input.to_string_lossy().to_string();
+
+        writer = spawn_blocking(move || -> Result<csv::Writer<File>> {
+            for peer in peers.iter() {
+                for message in peer.chunks.iter() {
+                    let date = message.date.to_rfc3339();
+                    let edited_at = message
+                        .edited_at
+                        .map(|date| date.to_rfc3339())
+                        .unwrap_or_default();
+                    let attachments = message
+                        .attachments
+                        .iter()
+                        .filter_map(|a| a.url.clone().or(a.file_id.clone()))
+                        .collect::<Vec<String>>()
+                        .join(" ");
+                    let reactions = message
+                        .reactions
+                        .iter()
+                        .map(|r| format!("{}:{}", r.emoji, r.count))
+                        .collect::<Vec<String>>()
+                        .join(" ");
+                    writer.write_record([
+                        neutralize_formula(&peer.peer.peer_id).as_ref(),
+                        neutralize_formula(&peer.peer.peer_username).as_ref(),
+                        neutralize_formula(&platform).as_ref(),
+                        neutralize_formula(&message.id).as_ref(),
+                        neutralize_formula(&message.user_id).as_ref(),
+                        neutralize_formula(&message.message).as_ref(),
+                        date.as_str(),
+                        message.reply_to.as_deref().unwrap_or_default(),
+                        edited_at.as_str(),
+                        neutralize_formula(message.forwarded_from.as_deref().unwrap_or_default())
+                            .as_ref(),
+                        neutralize_formula(&attachments).as_ref(),
+                        neutralize_formula(&reactions).as_ref(),
+                    ])?;
+                    progress.inc(1);
+                }
+            }
+
+            progress.finish_with_message(format!("{} - Exported", input_name));
+
+            Ok(writer)
+        })
+        .await??;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formulas_are_quoted() {
+        for cell in [
+            "=1+1",
+            "+SUM(A1:A2)",
+            "-2+3",
+            "@cmd",
+            "\t=1",
+            "\r=1",
+            "=HYPERLINK(\"http://x\")",
+        ] {
+            assert_eq!(neutralize_formula(cell), format!("'{}", cell), "{:?}", cell);
+        }
+    }
+
+    #[test]
+    fn plain_numbers_are_left_alone() {
+        for cell in ["-1001234567890", "+972", "-3.5", "-.5", "42"] {
+            assert_eq!(neutralize_formula(cell), cell, "{:?}", cell);
+        }
+    }
+
+    #[test]
+    fn special_floats_and_exponents_are_quoted() {
+        for cell in [
+            "-inf",
+            "-infinity",
+            "-NaN",
+            "+inf",
+            "-1e5",
+            "-",
+            "-.",
+            "-1.2.3",
+        ] {
+            assert_eq!(neutralize_formula(cell), format!("'{}", cell), "{:?}", cell);
+        }
+    }
+
+    #[test]
+    fn other_cells_are_left_alone() {
+        for cell in ["", "hello", "שלום", "a=b", "1-2"] {
+            assert!(
+                matches!(neutralize_formula(cell), Cow::Borrowed(_)),
+                "{:?}",
+                cell
+            );
+        }
+    }
+}
@@ -1,17 +1,27 @@
-use chrono::DateTime;
-use color_eyre::eyre::{Result, eyre};
+use color_eyre::eyre::Result;
 
 use crate::{
     arg::{
-        ApplicationCommands, DiscordCommands, PlatformCommands, TelegramCommands,
+        ApplicationCommands, DiscordCommands, ExportCommands, PlatformCommands, TelegramCommands,
         const_get_application_arguments,
     },
-    error::DateTimeParseError,
+    export::{export_csv, export_parquet},
+    migrate::migrate_dumps,
     platforms::{
-        discord::methods::dump_messages_for_channel,
-        telegram::methods::{dump_messages, resolve_usernames},
+        discord::{
+            methods::{
+                list_channels, list_dms, list_guilds, read_channel_threads, read_guild_channels,
+            },
+            structs::DiscordClient,
+        },
+        runner::{run_dump, run_dump_targets, run_sync},
+        structs::DumpOptions,
+        telegram::{
+            methods::{list_dialogs, resolve_usernames},
+            structs::{DialogFilter, TelegramClient},
+        },
     },
-    utils::{file_exists, file_extension_matches},
+    utils::{file_exists, file_extension_matches, timestamp_to_datetime},
 };
 
 pub async fn program_loader() -> Result<()> {
@@ -20,38 +30,90 @@ pub async fn program_loader() -> Result<()> {
     match &args.command {
         ApplicationCommands::Data { platform } => match platform {
             PlatformCommands::Telegram { method } => match method {
-                TelegramCommands::ResolveUsers { usernames } => {
+                TelegramCommands::Login { bot } => {
+                    TelegramClient::login(*bot).await?;
+                }
+                TelegramCommands::ResolveUsers {
+                    usernames,
+                    fail_on_error,
+                } => {
                     file_exists(usernames).await?;
                     file_extension_matches(usernames, "txt").await?;
 
-                    resolve_usernames(usernames.to_path_buf()).await?;
+                    resolve_usernames(usernames.to_path_buf(), *fail_on_error).await?;
                 }
                 TelegramCommands::DumpMessages {
                     users_file,
                     limit,
                     date_point,
+                    until,
                     reverse,
+                    resume,
+                    sink,
+                    fail_on_error,
+                    media,
+                } => {
+                    file_exists(users_file).await?;
+                    file_extension_matches(users_file, "json").await?;
+
+                    let date = timestamp_to_datetime(*date_point)?;
+                    let until = match until {
+                        Some(until) => Some(timestamp_to_datetime(*until)?),
+                        None => None,
+                    };
+
+                    run_dump::<TelegramClient>(
+                        users_file.to_path_buf(),
+                        DumpOptions::new(*limit, date, until, *reverse, *resume),
+                        sink.clone(),
+                        media.options(),
+                        *fail_on_error,
+                    )
+                    .await?;
+                }
+                TelegramCommands::ListDialogs {
+                    kinds,
+                    name,
+                    unread,
+                    archived,
+                    output,
                 } => {
-                    if *reverse {
-                        todo!(
-                            "Reverse operations via the Telegram library is not possible at this moment."
-                        )
+                    if let Some(output) = output {
+                        file_extension_matches(output, "json").await?;
                     }
 
+                    let filter = DialogFilter {
+                        kinds: kinds.clone(),
+                        name: name.clone(),
+                        unread: *unread,
+                        archived: *archived,
+                    };
+
+                    list_dialogs(filter, output.clone()).await?;
+                }
+                TelegramCommands::Sync {
+                    users_file,
+                    date_point,
+                    store,
+                    seed,
+                    fail_on_error,
+                } => {
                     file_exists(users_file).await?;
                     file_extension_matches(users_file, "json").await?;
 
-                    let date = match DateTime::from_timestamp_secs(*date_point) {
-                        Some(date) => date,
-                        None => {
-                            return Err(eyre!(DateTimeParseError::new(
-                                date_point.to_string(),
-                                "NO DATE_POINT FOUND AFTER PARSE".to_string()
-                            )));
-                        }
+                    let date = match date_point {
+                        Some(date_point) => Some(timestamp_to_datetime(*date_point)?),
+                        None => None,
                     };
 
-                    dump_messages(users_file.to_path_buf(), date, *limit, *reverse).await?;
+                    run_sync::<TelegramClient>(
+                        users_file.to_path_buf(),
+                        store.to_path_buf(),
+                        date,
+                        seed.clone(),
+                        *fail_on_error,
+                    )
+                    .await?;
                 }
             },
             PlatformCommands::Discord { method } => match method {
@@ -60,25 +122,157 @@ pub async fn program_loader() -> Result<()> {
                     limit,
                     date_point,
                     reverse,
+                    resume,
+                    sink,
+                    fail_on_error,
+                    media,
+                } => {
+                    file_exists(targets_file).await?;
+                    file_extension_matches(targets_file, "txt").await?;
+
+                    let date = timestamp_to_datetime(*date_point)?;
+
+                    run_dump::<DiscordClient>(
+                        targets_file.to_path_buf(),
+                        DumpOptions::new(*limit, date, None, *reverse, *resume),
+                        sink.clone(),
+                        media.options(),
+                        *fail_on_error,
+                    )
+                    .await?;
+                }
+                DiscordCommands::DumpGuild {
+                    guild,
+                    limit,
+                    date_point,
+                    reverse,
+                    resume,
+                    sink,
+                    fail_on_error,
+                    media,
+                } => {
+                    let date = timestamp_to_datetime(*date_point)?;
+
+                    let targets = read_guild_channels(guild.clone()).await?;
+
+                    run_dump_targets::<DiscordClient>(
+                        targets,
+                        DumpOptions::new(*limit, date, None, *reverse, *resume),
+                        sink.clone(),
+                        media.options(),
+                        *fail_on_error,
+                    )
+                    .await?;
+                }
+                DiscordCommands::DumpThreads {
+                    channel,
+                    private,
+                    limit,
+                    date_point,
+                    reverse,
+                    resume,
+                    sink,
+                    fail_on_error,
+                    media,
+                } => {
+                    let date = timestamp_to_datetime(*date_point)?;
+
+                    let targets = read_channel_threads(channel.clone(), *private).await?;
+
+                    run_dump_targets::<DiscordClient>(
+                        targets,
+                        DumpOptions::new(*limit, date, None, *reverse, *resume),
+                        sink.clone(),
+                        media.options(),
+                        *fail_on_error,
+                    )
+                    .await?;
+                }
+                DiscordCommands::ListGuilds => {
+                    list_guilds().await?;
+                }
+                DiscordCommands::ListChannels { guild, output } => {
+                    if let Some(output) = output {
+                        file_extension_matches(output, "txt").await?;
+                    }
+
+                    list_channels(guild.clone(), output.clone()).await?;
+                }
+                DiscordCommands::ListDms { output } => {
+                    if let Some(output) = output {
+                        file_extension_matches(output, "txt").await?;
+                    }
+
+                    list_dms(output.clone()).await?;
+                }
+                DiscordCommands::Sync {
+                    targets_file,
+                    date_point,
+                    store,
+                    seed,
+                    fail_on_error,
                 } => {
                     file_exists(targets_file).await?;
                     file_extension_matches(targets_file, "txt").await?;
 
-                    let date = match DateTime::from_timestamp_secs(*date_point) {
-                        Some(date) => date,
-                        None => {
-                            return Err(eyre!(DateTimeParseError::new(
-                                date_point.to_string(),
-                                "NO DATE_POINT FOUND AFTER PARSE".to_string()
-                            )));
-                        }
+                    let date = match date_point {
+                        Some(date_point) => Some(timestamp_to_datetime(*date_point)?),
+                        None => None,
                     };
 
-                    dump_messages_for_channel(targets_file.to_path_buf(), date, *limit, *reverse)
-                        .await?;
+                    run_sync::<DiscordClient>(
+                        targets_file.to_path_buf(),
+                        store.to_path_buf(),
+                        date,
+                        seed.clone(),
+                        *fail_on_error,
+                    )
+                    .await?;
                 }
             },
         },
+        ApplicationCommands::Export { format } => match format {
+            ExportCommands::Parquet {
+                inputs,
+                output,
+                platform,
+            } => {
+                for input in inputs.iter() {
+                    file_exists(input).await?;
+                }
+
+                export_parquet(inputs.clone(), output.to_path_buf(), platform.clone()).await?;
+            }
+            ExportCommands::Csv {
+                inputs,
+                output,
+                platform,
+                tsv,
+                bom,
+            } => {
+                for input in inputs.iter() {
+                    file_exists(input).await?;
+                }
+
+                let delimiter = if *tsv { b'\t' } else { b',' };
+
+                export_csv(
+                    inputs.clone(),
+                    output.to_path_buf(),
+                    platform.clone(),
+                    delimiter,
+                    *bom,
+                )
+                .await?;
+            }
+        },
+        ApplicationCommands::Migrate { inputs, in_place } => {
+            for input in inputs.iter() {
+                file_exists(input).await?;
+            }
+
+            migrate_dumps(inputs.clone(), *in_place).await?;
+        }
     }
 
     Ok(())
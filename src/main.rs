@@ -10,7 +10,9 @@ use crate::loader::program_loader;
 pub mod arg;
 pub mod env;
 pub mod error;
+pub mod export;
 pub mod loader;
+pub mod migrate;
 pub mod platforms;
 pub mod utils;
 pub mod visual;
@@ -0,0 +1,122 @@
+use std::{
+    fs::{File, rename},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::Result;
+use indicatif::ProgressBar;
+use serde_json::to_writer;
+use tokio::task::spawn_blocking;
+
+use crate::{
+    export::read_versioned_dump_file,
+    platforms::{
+        envelope::{DUMP_FORMAT_VERSION, DumpEnvelope},
+        sink::JsonLinesHeader,
+    },
+    visual::new_multi_progress,
+};
+
+fn is_json_lines(filepath: &Path) -> bool {
+    filepath
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("jsonl"))
+        .unwrap_or(false)
+}
+
+/// Where the upgraded copy of `input` goes, e.g. telegram_a_<time>.json -> telegram_a_<time>_v2.json.
+fn migrated_path(input: &Path) -> PathBuf {
+    let stem = input
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = input
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_string())
+        .unwrap_or_else(|| "json".to_string());
+
+    input.with_file_name(format!("{}_v{}.{}", stem, DUMP_FORMAT_VERSION, extension))
+}
+
+/// Writes the envelope in the same layout dump-messages would have, JSON lines files stay JSON lines.
+fn write_envelope(envelope: &DumpEnvelope, output: &PathBuf, json_lines: bool) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(output)?);
+
+    if json_lines {
+        to_writer(&mut writer, &envelope.metadata)?;
+        writer.write_all(b"\n")?;
+
+        for peer in envelope.peers.iter() {
+            to_writer(
+                &mut writer,
+                &JsonLinesHeader {
+                    peer: peer.peer.clone(),
+                },
+            )?;
+            writer.write_all(b"\n")?;
+
+            for message in peer.chunks.iter() {
+                to_writer(&mut writer, message)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+    } else {
+        to_writer(&mut writer, envelope)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Upgrades every dump in `inputs` to `DUMP_FORMAT_VERSION`, dumps already current are left alone.
+pub async fn migrate_dumps(inputs: Vec<PathBuf>, in_place: bool) -> Result<()> {
+    let (multiprog, style) = new_multi_progress()?;
+
+    for input in inputs.iter() {
+        let progress = multiprog.add(ProgressBar::new(1));
+        progress.set_style(style.clone());
+        progress.set_message(input.to_string_lossy().to_string());
+
+        let (version, envelope) = read_versioned_dump_file(input).await?;
+
+        if version == DUMP_FORMAT_VERSION {
+            progress.finish_with_message(format!(
+                "{} - Already at version {}",
+                input.to_string_lossy(),
+                DUMP_FORMAT_VERSION
+            ));
+            continue;
+        }
+
+        let json_lines = is_json_lines(input);
+        let output = if in_place {
+            input.with_extension("migrating")
+        } else {
+            migrated_path(input)
+        };
+
+        {
+            let output = output.clone();
+            spawn_blocking(move || write_envelope(&envelope, &output, json_lines)).await??;
+        }
+
+        let written = if in_place {
+            rename(&output, input)?;
+            input.clone()
+        } else {
+            output
+        };
+
+        progress.inc(1);
+        progress.finish_with_message(format!(
+            "{} - Migrated from version {} to {}",
+            written.to_string_lossy(),
+            version,
+            DUMP_FORMAT_VERSION
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,233 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_slice, to_string};
+use tokio::fs::{
+    OpenOptions, create_dir_all, read, read_dir, remove_dir, remove_file, rename, try_exists, write,
+};
+
+use crate::{
+    error::{CheckpointMismatchError, DeserializationError},
+    platforms::structs::{DumpOptions, PeerMessage},
+    utils::{append_json_lines, read_json_lines},
+};
+
+pub const CHECKPOINT_DIRECTORY: &str = "checkpoints";
+
+/// Part of the dump options a checkpoint was taken under, it only resumes a run over the same window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DumpWindow {
+    pub reverse: bool,
+    pub date_point: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<u32>,
+}
+
+impl DumpWindow {
+    /// Sync stores always walk forward from whatever they hold, whichever date point a run is given.
+    pub fn sync() -> Self {
+        Self {
+            reverse: true,
+            date_point: None,
+            until: None,
+            limit: None,
+        }
+    }
+}
+
+impl From<&DumpOptions> for DumpWindow {
+    fn from(options: &DumpOptions) -> Self {
+        Self {
+            reverse: options.reverse,
+            date_point: Some(options.date_point),
+            until: options.until,
+            limit: options.limit,
+        }
+    }
+}
+
+/// Position reached by a dump, rewritten after every page so an interrupted run can pick up from it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub target: String,
+    /// Missing from checkpoints written before windows were recorded
+    #[serde(default)]
+    pub window: Option<DumpWindow>,
+    pub last_message_id: String,
+    pub last_message_date: DateTime<Utc>,
+    pub fetched: usize,
+    /// File the output is appended to, a resumed run carries on writing it
+    #[serde(default)]
+    pub output: Option<PathBuf>,
+    /// Length of the output once the last page was in, anything written past it is dropped on resume
+    #[serde(default)]
+    pub output_len: Option<u64>,
+}
+
+impl Checkpoint {
+    pub fn new(
+        target: String,
+        window: DumpWindow,
+        last: &PeerMessage,
+        fetched: usize,
+        output: Option<(PathBuf, u64)>,
+    ) -> Self {
+        let (output, output_len) = output.unzip();
+
+        Self {
+            target,
+            window: Some(window),
+            last_message_id: last.id.clone(),
+            last_message_date: last.date,
+            fetched,
+            output,
+            output_len,
+        }
+    }
+}
+
+/// Checkpoint of a single target. Outputs that are appended to only need the cursor it holds, the others
+/// also get a journal of the messages fetched so far, kept as JSON lines, to be rebuilt from on resume.
+/// The sync command keeps the state of its persistent per-target stores the same way, it just never clears them.
+#[derive(Debug, Clone)]
+pub struct CheckpointStore {
+    target: String,
+    window: DumpWindow,
+    state_path: PathBuf,
+    messages_path: PathBuf,
+}
+
+impl CheckpointStore {
+    pub fn new(platform: &str, target: &str, window: DumpWindow) -> Self {
+        Self::with_directory(Path::new(CHECKPOINT_DIRECTORY), platform, target, window)
+    }
+
+    pub fn with_directory(
+        directory: &Path,
+        platform: &str,
+        target: &str,
+        window: DumpWindow,
+    ) -> Self {
+        Self {
+            target: target.to_string(),
+            window,
+            state_path: directory.join(format!("{}_{}.json", platform, target)),
+            messages_path: directory.join(format!("{}_{}.journal.jsonl", platform, target)),
+        }
+    }
+
+    /// Messages of a sync store, kept next to its state.
+    pub fn store_path(&self) -> PathBuf {
+        self.state_path.with_extension("jsonl")
+    }
+
+    /// Reads the last checkpoint, nothing when none exists.
+    /// A checkpoint taken over another window is refused rather than mixed into this run.
+    pub async fn load(&self) -> Result<Option<Checkpoint>> {
+        if !try_exists(&self.state_path).await? {
+            return Ok(None);
+        }
+
+        let checkpoint = {
+            let buf = read(&self.state_path).await?;
+            match from_slice::<Checkpoint>(&buf) {
+                Ok(data) => data,
+                Err(e) => {
+                    return Err(eyre!(DeserializationError::new(
+                        self.state_path.to_string_lossy().to_string(),
+                        e.to_string()
+                    )));
+                }
+            }
+        };
+
+        // Only sync stores predate recorded windows, and their window never changes
+        let same_window = match checkpoint.window {
+            Some(window) => window == self.window,
+            None => self.window == DumpWindow::sync(),
+        };
+        if !same_window {
+            return Err(eyre!(CheckpointMismatchError::new(
+                self.target.clone(),
+                self.state_path.to_string_lossy().to_string()
+            )));
+        }
+
+        Ok(Some(checkpoint))
+    }
+
+    /// Messages journaled up to `checkpoint`, for outputs that can't be picked up where they stopped.
+    pub async fn restore(&self, checkpoint: &Checkpoint) -> Result<Vec<PeerMessage>> {
+        if !try_exists(&self.messages_path).await? {
+            return Ok(vec![]);
+        }
+
+        // Messages journaled after the last checkpoint write are fetched again on resume
+        let mut messages = read_json_lines::<PeerMessage>(&self.messages_path).await?;
+        messages.truncate(checkpoint.fetched);
+
+        Ok(messages)
+    }
+
+    /// Drops any previous checkpoint so the target starts over.
+    pub async fn reset(&self) -> Result<()> {
+        if let Some(directory) = self.state_path.parent() {
+            create_dir_all(directory).await?;
+        }
+        self.clear().await
+    }
+
+    /// Journals a freshly fetched page ahead of the checkpoint covering it.
+    pub async fn journal(&self, page: &[PeerMessage]) -> Result<()> {
+        append_json_lines(&self.messages_path, page).await
+    }
+
+    /// Moves the checkpoint past `last`, `fetched` counts every message collected so far.
+    /// `output` is the file appended to and its length, for outputs written that way.
+    pub async fn record(
+        &self,
+        last: &PeerMessage,
+        fetched: usize,
+        output: Option<(PathBuf, u64)>,
+    ) -> Result<()> {
+        let checkpoint = Checkpoint::new(self.target.clone(), self.window, last, fetched, output);
+        let staging_path = self.state_path.with_extension("json.tmp");
+        write(&staging_path, to_string(&checkpoint)?).await?;
+        rename(&staging_path, &self.state_path).await?;
+
+        Ok(())
+    }
+
+    /// Removes the checkpoint once the target's output has been written.
+    pub async fn clear(&self) -> Result<()> {
+        for path in [&self.state_path, &self.messages_path] {
+            if try_exists(path).await? {
+                remove_file(path).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Cuts `filepath` back to `len` bytes, dropping whatever an interrupted run wrote past its last checkpoint.
+pub async fn truncate_output(filepath: &PathBuf, len: u64) -> Result<()> {
+    let file = OpenOptions::new().write(true).open(filepath).await?;
+    file.set_len(len).await?;
+
+    Ok(())
+}
+
+/// Removes the checkpoint directory once a run has cleared every checkpoint in it.
+pub async fn remove_checkpoint_directory() -> Result<()> {
+    let directory = Path::new(CHECKPOINT_DIRECTORY);
+    if !try_exists(directory).await? || read_dir(directory).await?.next_entry().await?.is_some() {
+        return Ok(());
+    }
+
+    remove_dir(directory).await?;
+
+    Ok(())
+}
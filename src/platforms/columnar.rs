@@ -0,0 +1,217 @@
+use std::{
+    fs::File,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use arrow_array::{ArrayRef, RecordBatch, StringArray, TimestampMillisecondArray};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use color_eyre::eyre::{Result, eyre};
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, ZstdLevel},
+    file::properties::WriterProperties,
+};
+use tokio::task::spawn_blocking;
+
+use crate::platforms::structs::{PeerMessage, ResolvedPeer};
+
+/// Rows per row group. Large enough that scans of multi-million message channels stay sequential,
+/// small enough that the writer's buffered group of message bodies fits comfortably in memory.
+pub const PARQUET_ROW_GROUP_SIZE: usize = 256 * 1024;
+
+/// One row per message, flattened with the peer it belongs to. Attachments, reactions and extra
+/// details are kept as JSON strings.
+pub fn message_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("peer_id", DataType::Utf8, false),
+        Field::new("peer_username", DataType::Utf8, false),
+        Field::new("peer_name", DataType::Utf8, true),
+        Field::new("parent_id", DataType::Utf8, true),
+        Field::new("parent_name", DataType::Utf8, true),
+        Field::new("thread_channel_id", DataType::Utf8, true),
+        Field::new("thread_starter_message_id", DataType::Utf8, true),
+        Field::new("platform", DataType::Utf8, false),
+        Field::new("id", DataType::Utf8, false),
+        Field::new("user_id", DataType::Utf8, false),
+        Field::new("message", DataType::Utf8, false),
+        Field::new(
+            "date",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("reply_to", DataType::Utf8, true),
+        Field::new(
+            "edited_at",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            true,
+        ),
+        Field::new("forwarded_from", DataType::Utf8, true),
+        Field::new("attachments", DataType::Utf8, false),
+        Field::new("reactions", DataType::Utf8, false),
+        Field::new("extra", DataType::Utf8, false),
+    ]))
+}
+
+fn record_batch(
+    schema: SchemaRef,
+    platform: &str,
+    peer: &ResolvedPeer,
+    messages: &[PeerMessage],
+) -> Result<RecordBatch> {
+    let repeated = |value: &str| -> ArrayRef {
+        Arc::new(StringArray::from_iter_values(std::iter::repeat_n(
+            value,
+            messages.len(),
+        )))
+    };
+    let repeated_optional = |value: Option<&str>| -> ArrayRef {
+        Arc::new(StringArray::from_iter(std::iter::repeat_n(
+            value,
+            messages.len(),
+        )))
+    };
+    let column = |value: fn(&PeerMessage) -> &str| -> ArrayRef {
+        Arc::new(StringArray::from_iter_values(messages.iter().map(value)))
+    };
+
+    let optional = |value: fn(&PeerMessage) -> Option<&String>| -> ArrayRef {
+        Arc::new(StringArray::from_iter(messages.iter().map(value)))
+    };
+    let json = |value: fn(&PeerMessage) -> serde_json::Result<String>| -> Result<ArrayRef> {
+        Ok(Arc::new(StringArray::from_iter_values(
+            messages
+                .iter()
+                .map(value)
+                .collect::<serde_json::Result<Vec<String>>>()?,
+        )))
+    };
+
+    let columns: Vec<ArrayRef> = vec![
+        repeated(&peer.peer_id),
+        repeated(&peer.peer_username),
+        repeated_optional(peer.peer_name.as_deref()),
+        repeated_optional(peer.parent.as_ref().map(|parent| parent.id.as_str())),
+        repeated_optional(peer.parent.as_ref().map(|parent| parent.name.as_str())),
+        repeated_optional(
+            peer.thread
+                .as_ref()
+                .map(|thread| thread.channel_id.as_str()),
+        ),
+        repeated_optional(
+            peer.thread
+                .as_ref()
+                .and_then(|thread| thread.starter_message_id.as_deref()),
+        ),
+        repeated(platform),
+        column(|m| &m.id),
+        column(|m| &m.user_id),
+        column(|m| &m.message),
+        Arc::new(
+            TimestampMillisecondArray::from_iter_values(
+                messages.iter().map(|m| m.date.timestamp_millis()),
+            )
+            .with_timezone("UTC"),
+        ),
+        optional(|m| m.reply_to.as_ref()),
+        Arc::new(
+            TimestampMillisecondArray::from_iter(
+                messages
+                    .iter()
+                    .map(|m| m.edited_at.map(|date| date.timestamp_millis())),
+            )
+            .with_timezone("UTC"),
+        ),
+        optional(|m| m.forwarded_from.as_ref()),
+        json(|m| serde_json::to_string(&m.attachments))?,
+        json(|m| serde_json::to_string(&m.reactions))?,
+        json(|m| serde_json::to_string(&m.extra))?,
+    ];
+
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+/// Parquet file shared by every target of a run, rows are buffered by the writer into row groups.
+#[derive(Debug, Clone)]
+pub struct ParquetStore {
+    writer: Arc<Mutex<Option<ArrowWriter<File>>>>,
+    schema: SchemaRef,
+    platform: String,
+}
+
+impl ParquetStore {
+    pub async fn create(path: PathBuf, platform: &str) -> Result<Self> {
+        let schema = message_schema();
+
+        let writer = {
+            let schema = schema.clone();
+            spawn_blocking(move || -> Result<ArrowWriter<File>> {
+                let properties = WriterProperties::builder()
+                    .set_max_row_group_size(PARQUET_ROW_GROUP_SIZE)
+                    .set_compression(Compression::ZSTD(ZstdLevel::default()))
+                    .build();
+
+                Ok(ArrowWriter::try_new(
+                    File::create(path)?,
+                    schema,
+                    Some(properties),
+                )?)
+            })
+            .await??
+        };
+
+        Ok(Self {
+            writer: Arc::new(Mutex::new(Some(writer))),
+            schema,
+            platform: platform.to_string(),
+        })
+    }
+
+    /// Same file, rows tagged with another platform. Used when exporting dumps of several platforms together.
+    pub fn for_platform(&self, platform: &str) -> Self {
+        Self {
+            writer: self.writer.clone(),
+            schema: self.schema.clone(),
+            platform: platform.to_string(),
+        }
+    }
+
+    pub async fn write(&self, peer: &ResolvedPeer, messages: Vec<PeerMessage>) -> Result<()> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+
+        let batch = record_batch(self.schema.clone(), &self.platform, peer, &messages)?;
+        let writer = self.writer.clone();
+
+        spawn_blocking(move || -> Result<()> {
+            let mut guard = writer
+                .lock()
+                .map_err(|e| eyre!(format!("Parquet writer poisoned: {}", e)))?;
+
+            match guard.as_mut() {
+                Some(writer) => Ok(writer.write(&batch)?),
+                None => Err(eyre!("Parquet writer already closed")),
+            }
+        })
+        .await?
+    }
+
+    /// Flushes the last row group and writes the footer, the file is unreadable until this runs.
+    pub async fn close(&self) -> Result<()> {
+        let writer = self.writer.clone();
+
+        spawn_blocking(move || -> Result<()> {
+            let mut guard = writer
+                .lock()
+                .map_err(|e| eyre!(format!("Parquet writer poisoned: {}", e)))?;
+
+            if let Some(writer) = guard.take() {
+                writer.close()?;
+            }
+
+            Ok(())
+        })
+        .await?
+    }
+}
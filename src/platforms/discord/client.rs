@@ -4,16 +4,17 @@ use color_eyre::eyre::{Result, eyre};
 use tokio::sync::OnceCell;
 
 use crate::{
-    error::DiscordClientInitializationError, platforms::discord::structs::DiscordClient,
-    utils::get_discord_headermap,
+    env::Environment, error::DiscordClientInitializationError,
+    platforms::discord::structs::DiscordClient, utils::get_discord_headermap,
 };
 
-pub const DISCORD_CLIENT: OnceCell<Arc<DiscordClient>> = OnceCell::const_new();
+pub static DISCORD_CLIENT: OnceCell<Arc<DiscordClient>> = OnceCell::const_new();
 
 async fn init_discord_client() -> Result<Arc<DiscordClient>> {
     let headers = get_discord_headermap().await?;
+    let env = Environment::read()?;
 
-    let client = match DiscordClient::init(headers).await {
+    let client = match DiscordClient::init(headers, &env.discord).await {
         Ok(c) => c,
         Err(e) => {
             eprintln!("{}", e);
@@ -0,0 +1,413 @@
+use std::time::Duration;
+
+use color_eyre::eyre::{Result, eyre};
+use dashmap::DashMap;
+use reqwest::{
+    Client, Response, StatusCode,
+    header::{HeaderMap, RETRY_AFTER},
+};
+use serde::Deserialize;
+use serde_json::from_str;
+use tokio::{
+    sync::RwLock,
+    time::{Instant, sleep, sleep_until},
+};
+
+use crate::error::{DiscordAPIError, DiscordResponseError};
+
+/// Attempts given to a request answered with a 5xx before giving up.
+const SERVER_ERROR_ATTEMPTS: u32 = 5;
+
+/// Rate limited responses retried before giving up, Discord keeps these short unless the token is abused.
+const RATE_LIMITED_ATTEMPTS: u32 = 10;
+
+/// First 5xx backoff, doubled on every further failure.
+const SERVER_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Body of a 429 response.
+#[derive(Debug, Deserialize)]
+struct RateLimitedBody {
+    retry_after: f64,
+    #[serde(default)]
+    global: bool,
+}
+
+/// JSON error object Discord answers failed requests with.
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    code: u32,
+    message: String,
+}
+
+/// Requests left in a bucket and when it refills.
+#[derive(Debug, Clone, Copy)]
+struct BucketState {
+    remaining: u64,
+    reset_at: Instant,
+}
+
+/// Request layer in front of the Discord REST API. Tracks the buckets Discord reports in the
+/// `X-RateLimit-*` headers, waits out exhausted buckets and global limits before sending, retries
+/// 429s after `retry_after` and 5xx responses with exponential backoff.
+#[derive(Debug)]
+pub struct DiscordHttp {
+    client: Client,
+    base_url: String,
+    /// Route key to the bucket hash Discord assigned it, routes share a bucket when the hash matches
+    route_buckets: DashMap<String, String>,
+    /// Bucket hash (or route key, until the hash is known) to its state
+    buckets: DashMap<String, BucketState>,
+    global_reset_at: RwLock<Option<Instant>>,
+}
+
+impl DiscordHttp {
+    /// `client` is expected to carry the auth headers as defaults, every request goes through it.
+    pub fn new(base_url: String, client: Client) -> Self {
+        Self {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            route_buckets: DashMap::new(),
+            buckets: DashMap::new(),
+            global_reset_at: RwLock::new(None),
+        }
+    }
+
+    fn bucket_key(&self, route: &str) -> String {
+        self.route_buckets
+            .get(route)
+            .map(|bucket| format!("{}:{}", bucket.value(), route_major_parameter(route)))
+            .unwrap_or_else(|| route.to_string())
+    }
+
+    /// Waits out a global limit and the route's bucket, then takes a request from the bucket.
+    async fn acquire(&self, route: &str) {
+        if let Some(reset_at) = *self.global_reset_at.read().await {
+            sleep_until(reset_at).await;
+        }
+
+        let key = self.bucket_key(route);
+
+        let wait_until = match self.buckets.get_mut(&key) {
+            Some(mut bucket) => {
+                let now = Instant::now();
+                if bucket.reset_at <= now {
+                    None
+                } else if bucket.remaining == 0 {
+                    Some(bucket.reset_at)
+                } else {
+                    bucket.remaining -= 1;
+                    None
+                }
+            }
+            None => None,
+        };
+
+        if let Some(reset_at) = wait_until {
+            sleep_until(reset_at).await;
+        }
+    }
+
+    /// Records the bucket state from a response's rate limit headers.
+    fn update(&self, route: &str, headers: &HeaderMap) {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+        if let Some(bucket) = header("x-ratelimit-bucket") {
+            self.route_buckets
+                .insert(route.to_string(), bucket.to_string());
+        }
+
+        let remaining = header("x-ratelimit-remaining").and_then(|value| value.parse::<u64>().ok());
+        let reset_after =
+            header("x-ratelimit-reset-after").and_then(|value| value.parse::<f64>().ok());
+
+        if let (Some(remaining), Some(reset_after)) = (remaining, reset_after) {
+            self.buckets.insert(
+                self.bucket_key(route),
+                BucketState {
+                    remaining,
+                    reset_at: Instant::now() + Duration::from_secs_f64(reset_after.max(0f64)),
+                },
+            );
+        }
+    }
+
+    /// How long a 429 asks to wait, global limits hold back every route until they pass.
+    async fn rate_limited(&self, route: &str, response: Response) -> Duration {
+        let headers = response.headers().clone();
+        self.update(route, &headers);
+
+        let header_retry = headers
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<f64>().ok());
+        let header_global = headers
+            .get("x-ratelimit-global")
+            .map(|value| value.as_bytes().eq_ignore_ascii_case(b"true"))
+            .unwrap_or(false);
+
+        let body = response.json::<RateLimitedBody>().await.ok();
+
+        let retry_after = body
+            .as_ref()
+            .map(|body| body.retry_after)
+            .or(header_retry)
+            .unwrap_or(1f64);
+        let wait = Duration::from_secs_f64(retry_after.max(0f64));
+
+        if header_global || body.map(|body| body.global).unwrap_or(false) {
+            *self.global_reset_at.write().await = Some(Instant::now() + wait);
+        } else {
+            self.buckets.insert(
+                self.bucket_key(route),
+                BucketState {
+                    remaining: 0,
+                    reset_at: Instant::now() + wait,
+                },
+            );
+        }
+
+        wait
+    }
+
+    /// Sends a GET for `path` (relative to the API base, query included) and returns the body of the
+    /// first successful response.
+    pub async fn get(&self, route: &str, path: &str) -> Result<String> {
+        let url = format!("{}{}", self.base_url, path);
+
+        let mut server_errors = 0u32;
+        let mut rate_limits = 0u32;
+
+        loop {
+            self.acquire(route).await;
+
+            let response = match self.client.get(url.clone()).send().await {
+                Ok(response) => response,
+                Err(e)
+                    if (e.is_connect() || e.is_timeout())
+                        && server_errors + 1 < SERVER_ERROR_ATTEMPTS =>
+                {
+                    sleep(SERVER_ERROR_BACKOFF * 2u32.pow(server_errors)).await;
+                    server_errors += 1;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            let status = response.status();
+
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                rate_limits += 1;
+                if rate_limits >= RATE_LIMITED_ATTEMPTS {
+                    return Err(eyre!(DiscordResponseError::new(
+                        url,
+                        status.as_u16(),
+                        "still rate limited after retrying".to_string()
+                    )));
+                }
+
+                let wait = self.rate_limited(route, response).await;
+                sleep(wait).await;
+                continue;
+            }
+
+            if status.is_server_error() {
+                server_errors += 1;
+                if server_errors >= SERVER_ERROR_ATTEMPTS {
+                    return Err(eyre!(DiscordResponseError::new(
+                        url,
+                        status.as_u16(),
+                        response.text().await.unwrap_or_default()
+                    )));
+                }
+
+                sleep(SERVER_ERROR_BACKOFF * 2u32.pow(server_errors - 1)).await;
+                continue;
+            }
+
+            self.update(route, response.headers());
+
+            if !status.is_success() {
+                let body = response.text().await.unwrap_or_default();
+
+                return Err(match from_str::<ErrorBody>(&body) {
+                    Ok(error) => eyre!(DiscordAPIError::new(
+                        route.to_string(),
+                        status.as_u16(),
+                        error.code,
+                        error.message
+                    )),
+                    Err(_) => eyre!(DiscordResponseError::new(url, status.as_u16(), body)),
+                });
+            }
+
+            return Ok(response.text().await?);
+        }
+    }
+}
+
+/// Buckets are scoped per major parameter (channel, guild or webhook id), the first id in a route key.
+fn route_major_parameter(route: &str) -> &str {
+    route
+        .split('/')
+        .find(|segment| segment.chars().all(|c| c.is_ascii_digit()) && !segment.is_empty())
+        .unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    const ROUTE: &str = "/channels/1/messages";
+
+    fn response(status: &str, headers: &[(&str, &str)], body: &str) -> String {
+        let mut head = format!(
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            status,
+            body.len()
+        );
+        for (name, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+        format!("{}\r\n{}", head, body)
+    }
+
+    /// Answers one connection per response, in order, and records the request line of each.
+    async fn serve(responses: Vec<String>) -> (DiscordHttp, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+
+                let mut request = vec![];
+                let mut buf = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..read]);
+                }
+
+                let request = String::from_utf8_lossy(&request);
+                seen.lock()
+                    .unwrap()
+                    .push(request.lines().next().unwrap_or_default().to_string());
+
+                stream.write_all(response.as_bytes()).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+
+        (DiscordHttp::new(base_url, Client::new()), requests)
+    }
+
+    #[tokio::test]
+    async fn route_rate_limit_is_retried_after_the_wait() {
+        let (http, requests) = serve(vec![
+            response(
+                "429 Too Many Requests",
+                &[("Content-Type", "application/json")],
+                r#"{"retry_after": 0.2, "global": false}"#,
+            ),
+            response("200 OK", &[], "[]"),
+        ])
+        .await;
+
+        let started = Instant::now();
+        let body = http
+            .get(ROUTE, "/channels/1/messages?limit=1")
+            .await
+            .unwrap();
+
+        assert_eq!(body, "[]");
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        assert!(http.global_reset_at.read().await.is_none());
+        assert!(http.buckets.contains_key(ROUTE));
+    }
+
+    #[tokio::test]
+    async fn global_rate_limit_holds_back_every_route() {
+        let (http, requests) = serve(vec![
+            response(
+                "429 Too Many Requests",
+                &[
+                    ("Content-Type", "application/json"),
+                    ("X-RateLimit-Global", "true"),
+                ],
+                r#"{"retry_after": 0.2, "global": true}"#,
+            ),
+            response("200 OK", &[], "[]"),
+        ])
+        .await;
+
+        let started = Instant::now();
+        let body = http
+            .get(ROUTE, "/channels/1/messages?limit=1")
+            .await
+            .unwrap();
+
+        assert_eq!(body, "[]");
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        assert!(http.global_reset_at.read().await.is_some());
+        assert!(!http.buckets.contains_key(ROUTE));
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried_with_backoff() {
+        let (http, requests) = serve(vec![
+            response("502 Bad Gateway", &[], "upstream"),
+            response("200 OK", &[], "[]"),
+        ])
+        .await;
+
+        let started = Instant::now();
+        let body = http
+            .get(ROUTE, "/channels/1/messages?limit=1")
+            .await
+            .unwrap();
+
+        assert_eq!(body, "[]");
+        assert!(started.elapsed() >= SERVER_ERROR_BACKOFF);
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let (http, requests) = serve(vec![response(
+            "403 Forbidden",
+            &[("Content-Type", "application/json")],
+            r#"{"message": "Missing Access", "code": 50001}"#,
+        )])
+        .await;
+
+        let error = http
+            .get(ROUTE, "/channels/1/messages?limit=1")
+            .await
+            .unwrap_err();
+
+        let error = error.downcast_ref::<DiscordAPIError>().unwrap();
+        assert_eq!((error.status, error.code), (403, 50001));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn major_parameter_is_the_first_id() {
+        assert_eq!(route_major_parameter("/channels/1/messages"), "1");
+        assert_eq!(route_major_parameter("/guilds/2/channels"), "2");
+        assert_eq!(route_major_parameter("/users/@me/guilds"), "");
+    }
+}
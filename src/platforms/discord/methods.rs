@@ -1,116 +1,233 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use chrono::{DateTime, Utc};
-use color_eyre::eyre::{Result, eyre};
+use color_eyre::eyre::{Report, Result, eyre};
 use encoding_rs::UTF_8;
-use futures::future::try_join_all;
 use indicatif::ProgressBar;
-use serde_json::to_string;
-use tokio::fs::{read, write};
+use reqwest::Client;
+use tokio::{
+    fs::{read, write},
+    sync::Semaphore,
+};
 
 use crate::{
-    error::DecodingError,
+    error::{DecodingError, DiscordAPIError},
     platforms::{
-        discord::{client::const_get_discord_client, structs::DiscordClient},
-        structs::{DumpedPeer, PeerMessage, ResolvedPeer},
+        discord::{
+            client::const_get_discord_client,
+            structs::{DiscordClient, MESSAGE_CHANNEL_KINDS, channel_kind_name},
+        },
+        platform::Platform,
+        sink::PeerSink,
+        structs::{DumpOptions, ResolvedPeer},
     },
-    visual::new_multi_progress,
+    visual::print_table,
 };
 
-async fn dump_for_single_channel(
-    client: Arc<DiscordClient>,
-    channel: String,
-    date_point: DateTime<Utc>,
-    limit: u64,
-    reverse: bool,
-    progress: Option<ProgressBar>,
-) -> Result<DumpedPeer> {
-    let messages = client
-        .get_messages(channel.clone(), limit as usize, progress)
-        .await?
-        .iter()
-        .map(|m| {
-            PeerMessage::new(
-                m.id.clone(),
-                format!("user{}", m.author.id.clone()),
-                m.content.clone(),
-                DateTime::parse_from_rfc3339(&m.timestamp)
-                    .unwrap()
-                    .with_timezone(&Utc),
-            )
-        })
-        .collect::<Vec<PeerMessage>>();
+async fn read_target_channels(targets_file: &Path) -> Result<Vec<ResolvedPeer>> {
+    let buf = read(targets_file).await?;
+    let (string, _, error) = UTF_8.decode(&buf);
+
+    if error {
+        return Err(eyre!(DecodingError::new(
+            targets_file.to_string_lossy().to_string()
+        )));
+    }
 
-    Ok(DumpedPeer::new(
-        ResolvedPeer::new(channel.clone(), channel.clone()),
-        messages,
-    ))
+    Ok(string
+        .lines()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| ResolvedPeer::new(s.to_string(), s.to_string()))
+        .collect::<Vec<ResolvedPeer>>())
 }
 
-pub async fn dump_messages_for_channel(
-    targets_file: PathBuf,
-    date_point: DateTime<Utc>,
-    limit: Option<u32>,
-    reverse: bool,
-) -> Result<()> {
-    let execution_time = Utc::now();
-
-    let target_channels = {
-        let buf = read(&targets_file).await?;
-        let (string, _, error) = UTF_8.decode(&buf);
-
-        if error {
-            return Err(eyre!(DecodingError::new(
-                targets_file.to_string_lossy().to_string()
-            )));
+impl Platform for DiscordClient {
+    type Target = ResolvedPeer;
+
+    const NAME: &'static str = "discord";
+
+    // Every channel is paged at once, same as before the runner was shared
+    const CONCURRENCY: usize = Semaphore::MAX_PERMITS;
+
+    const SINGLE_JSON_DOCUMENT: bool = true;
+
+    async fn client() -> Result<Arc<Self>> {
+        const_get_discord_client().await
+    }
+
+    async fn list(targets_file: &Path) -> Result<Vec<ResolvedPeer>> {
+        read_target_channels(targets_file).await
+    }
+
+    fn label(target: &ResolvedPeer) -> String {
+        match (&target.parent, &target.peer_name) {
+            (Some(guild), Some(name)) => format!("{} #{}", guild.name, name),
+            _ => target.peer_id.clone(),
         }
+    }
 
-        string
-            .lines()
-            .map(|s| s.to_string())
-            .collect::<Vec<String>>()
-    };
+    fn media_client(&self) -> Option<Client> {
+        Some(self.attachment_client())
+    }
 
-    let client = const_get_discord_client().await?;
+    async fn resolve(&self, target: &ResolvedPeer) -> Result<ResolvedPeer> {
+        Ok(target.clone())
+    }
 
-    let (multiprog, style) = new_multi_progress()?;
+    fn target_failure(error: &Report) -> Option<String> {
+        error
+            .downcast_ref::<DiscordAPIError>()
+            .filter(|error| error.kind.is_target_specific())
+            .map(|error| error.reason())
+    }
 
-    let actual_limit = match limit {
-        Some(lim) => lim as u64,
-        None => 1e+8 as u64,
-    };
+    async fn dump(
+        &self,
+        target: &ResolvedPeer,
+        options: &DumpOptions,
+        sink: &mut PeerSink,
+        progress: Option<ProgressBar>,
+    ) -> Result<()> {
+        self.get_messages(target.peer_id.clone(), options, sink, progress)
+            .await
+    }
+}
+
+/// Dump targets for every message bearing channel of `guild_id`.
+pub async fn read_guild_channels(guild_id: String) -> Result<Vec<ResolvedPeer>> {
+    const_get_discord_client()
+        .await?
+        .get_guild_targets(guild_id)
+        .await
+}
+
+/// Dump targets for the threads under `channel_id`, archived private ones only when `include_private`.
+pub async fn read_channel_threads(
+    channel_id: String,
+    include_private: bool,
+) -> Result<Vec<ResolvedPeer>> {
+    const_get_discord_client()
+        .await?
+        .get_thread_targets(channel_id, include_private)
+        .await
+}
+
+/// Writes channel ids one per line, the layout dump-messages reads its targets from.
+/// No ids leave the file empty.
+async fn write_targets_file(output: &PathBuf, ids: Vec<String>) -> Result<()> {
+    let lines = ids.iter().map(|id| format!("{}\n", id)).collect::<String>();
+    write(output, lines).await?;
+
+    Ok(())
+}
+
+pub async fn list_guilds() -> Result<()> {
+    let guilds = const_get_discord_client()
+        .await?
+        .get_joined_guilds()
+        .await?;
 
-    let futures = target_channels
+    let rows = guilds
+        .into_iter()
+        .map(|guild| {
+            vec![
+                guild.id,
+                guild.name,
+                if guild.owner.unwrap_or(false) {
+                    "yes"
+                } else {
+                    "no"
+                }
+                .to_string(),
+            ]
+        })
+        .collect::<Vec<Vec<String>>>();
+
+    print_table(&["ID", "NAME", "OWNER"], &rows);
+
+    Ok(())
+}
+
+/// Prints the channels of a guild. With `output`, the ones holding messages are written there as a targets file instead.
+pub async fn list_channels(guild_id: String, output: Option<PathBuf>) -> Result<()> {
+    let channels = const_get_discord_client()
+        .await?
+        .get_guild_channels(guild_id)
+        .await?;
+
+    if let Some(output) = output {
+        let ids = channels
+            .into_iter()
+            .filter(|channel| MESSAGE_CHANNEL_KINDS.contains(&channel.kind))
+            .map(|channel| channel.id)
+            .collect();
+
+        return write_targets_file(&output, ids).await;
+    }
+
+    let categories = channels
+        .iter()
+        .filter(|channel| channel.kind == 4)
+        .map(|channel| (channel.id.clone(), channel.name.clone().unwrap_or_default()))
+        .collect::<Vec<(String, String)>>();
+
+    let rows = channels
         .iter()
-        .map(|c| {
-            let progress = multiprog.add(ProgressBar::new(actual_limit));
-            progress.set_style(style.clone());
-            progress.set_message(c.clone());
-            dump_for_single_channel(
-                client.clone(),
-                c.to_string(),
-                date_point.clone(),
-                actual_limit.clone(),
-                reverse,
-                Some(progress),
-            )
+        .map(|channel| {
+            let category = channel
+                .parent_id
+                .as_ref()
+                .and_then(|parent| categories.iter().find(|(id, _)| id == parent))
+                .map(|(_, name)| name.clone())
+                .unwrap_or_default();
+
+            vec![
+                channel.id.clone(),
+                channel_kind_name(channel.kind).to_string(),
+                channel.name.clone().unwrap_or_default(),
+                category,
+            ]
+        })
+        .collect::<Vec<Vec<String>>>();
+
+    print_table(&["ID", "TYPE", "NAME", "CATEGORY"], &rows);
+
+    Ok(())
+}
+
+/// Prints open DMs and group DMs, or writes their ids to `output` as a targets file.
+pub async fn list_dms(output: Option<PathBuf>) -> Result<()> {
+    let channels = const_get_discord_client().await?.get_dm_channels().await?;
+
+    if let Some(output) = output {
+        let ids = channels.into_iter().map(|channel| channel.id).collect();
+
+        return write_targets_file(&output, ids).await;
+    }
+
+    let rows = channels
+        .into_iter()
+        .map(|channel| {
+            let recipients = channel
+                .recipients
+                .iter()
+                .map(|user| user.username.clone())
+                .collect::<Vec<String>>()
+                .join(", ");
+
+            vec![
+                channel.id,
+                channel_kind_name(channel.kind).to_string(),
+                channel.name.unwrap_or(recipients),
+                channel.last_message_id.unwrap_or_default(),
+            ]
         })
-        .collect::<Vec<_>>();
-
-    let results = try_join_all(futures).await?;
-
-    write(
-        format!(
-            "discord_dumped_peers_{}_{}.json",
-            results.len(),
-            &&execution_time
-                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
-                .replace("+", "_")
-                .replace(":", "_")
-        ),
-        to_string(&results)?,
-    )
-    .await?;
+        .collect::<Vec<Vec<String>>>();
+
+    print_table(&["ID", "TYPE", "NAME", "LAST_MESSAGE_ID"], &rows);
 
     Ok(())
 }
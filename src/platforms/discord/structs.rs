@@ -1,11 +1,27 @@
-use std::time::Duration;
+use std::{
+    collections::{BTreeMap, HashSet},
+    time::Duration,
+};
 
-use color_eyre::eyre::Result;
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Report, Result, eyre};
 use indicatif::ProgressBar;
-use reqwest::{Client, header::HeaderMap};
+use reqwest::{Client, ClientBuilder, Proxy, header::HeaderMap};
 use serde::{Deserialize, Serialize};
 use serde_json::from_str;
-use tokio::time::sleep;
+
+use crate::{
+    env::DiscordEnvironment,
+    error::{DateTimeParseError, DiscordAPIError},
+    platforms::{
+        discord::http::DiscordHttp,
+        sink::PeerSink,
+        structs::{
+            Attachment, AttachmentKind, DumpOptions, PeerMessage, PeerParent, Reaction,
+            ResolvedPeer, ThreadOrigin,
+        },
+    },
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
@@ -33,6 +49,37 @@ pub struct PartialGuild {
     pub permissions: Option<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageAttachment {
+    pub id: String,
+    pub filename: String,
+    pub size: u64,
+    pub url: String,
+    pub content_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Emoji {
+    pub id: Option<String>,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageReaction {
+    pub count: u32,
+    pub emoji: Emoji,
+}
+
+/// Reference type `1` marks a forward, anything else (or no type) a reply.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageReference {
+    #[serde(rename = "type", default)]
+    pub kind: u8,
+    pub message_id: Option<String>,
+    pub channel_id: Option<String>,
+    pub guild_id: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub id: String,
@@ -40,6 +87,116 @@ pub struct Message {
     pub author: User,
     pub content: String,
     pub timestamp: String,
+    #[serde(rename = "type", default)]
+    pub kind: u8,
+    #[serde(default)]
+    pub edited_timestamp: Option<String>,
+    #[serde(default)]
+    pub attachments: Vec<MessageAttachment>,
+    #[serde(default)]
+    pub reactions: Vec<MessageReaction>,
+    #[serde(default)]
+    pub message_reference: Option<MessageReference>,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub webhook_id: Option<String>,
+    #[serde(default)]
+    pub embeds: Vec<serde_json::Value>,
+}
+
+fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+impl From<&MessageAttachment> for Attachment {
+    fn from(attachment: &MessageAttachment) -> Self {
+        Attachment {
+            kind: attachment
+                .content_type
+                .as_deref()
+                .map(AttachmentKind::from_mime_type)
+                .unwrap_or(AttachmentKind::Document),
+            filename: Some(attachment.filename.clone()),
+            mime_type: attachment.content_type.clone(),
+            size: Some(attachment.size),
+            url: Some(attachment.url.clone()),
+            file_id: Some(attachment.id.clone()),
+            sha256: None,
+            download_error: None,
+        }
+    }
+}
+
+impl From<&MessageReaction> for Reaction {
+    fn from(reaction: &MessageReaction) -> Self {
+        let emoji = match (&reaction.emoji.id, &reaction.emoji.name) {
+            (Some(id), Some(name)) => format!("{}:{}", name, id),
+            (Some(id), None) => format!("custom{}", id),
+            (None, Some(name)) => name.clone(),
+            (None, None) => "unknown".to_string(),
+        };
+
+        Reaction::new(emoji, reaction.count)
+    }
+}
+
+/// Fails on a message whose timestamp isn't RFC 3339, rather than guessing when it was sent.
+impl TryFrom<&Message> for PeerMessage {
+    type Error = Report;
+
+    fn try_from(message: &Message) -> Result<Self> {
+        let date = parse_timestamp(&message.timestamp).ok_or_else(|| {
+            eyre!(DateTimeParseError::new(
+                message.timestamp.clone(),
+                format!("timestamp of message {} is not RFC 3339", message.id)
+            ))
+        })?;
+
+        let (reply_to, forwarded_from) = match &message.message_reference {
+            Some(reference) if reference.kind == 1 => (
+                None,
+                reference
+                    .channel_id
+                    .as_ref()
+                    .map(|channel| format!("channel{}", channel)),
+            ),
+            Some(reference) => (reference.message_id.clone(), None),
+            None => (None, None),
+        };
+
+        let mut extra = BTreeMap::new();
+        extra.insert("type".to_string(), message.kind.to_string());
+        if message.pinned {
+            extra.insert("pinned".to_string(), true.to_string());
+        }
+        if let Some(webhook_id) = &message.webhook_id {
+            extra.insert("webhook_id".to_string(), webhook_id.clone());
+        }
+        if !message.embeds.is_empty() {
+            extra.insert("embeds".to_string(), message.embeds.len().to_string());
+        }
+
+        Ok(PeerMessage {
+            reply_to,
+            edited_at: message
+                .edited_timestamp
+                .as_deref()
+                .and_then(parse_timestamp),
+            forwarded_from,
+            attachments: message.attachments.iter().map(Attachment::from).collect(),
+            reactions: message.reactions.iter().map(Reaction::from).collect(),
+            extra,
+            ..PeerMessage::new(
+                message.id.clone(),
+                format!("user{}", message.author.id.clone()),
+                message.content.clone(),
+                date,
+            )
+        })
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -49,22 +206,103 @@ pub struct Channel {
     pub name: Option<String>,
     #[serde(rename = "type")]
     pub kind: u8,
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    #[serde(default)]
+    pub thread_metadata: Option<ThreadMetadata>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadMetadata {
+    pub archived: bool,
+    pub archive_timestamp: String,
+}
+
+/// Channel types whose history can be read through the messages endpoint: text and announcement
+/// channels and the threads under them or under forums.
+pub const MESSAGE_CHANNEL_KINDS: [u8; 5] = [
+    0,  // GUILD_TEXT
+    5,  // GUILD_ANNOUNCEMENT
+    10, // ANNOUNCEMENT_THREAD
+    11, // PUBLIC_THREAD
+    12, // PRIVATE_THREAD
+];
+
+/// Readable name of a channel type, for listings.
+pub fn channel_kind_name(kind: u8) -> &'static str {
+    match kind {
+        0 => "text",
+        1 => "dm",
+        2 => "voice",
+        3 => "group_dm",
+        4 => "category",
+        5 => "announcement",
+        10 => "announcement_thread",
+        11 => "public_thread",
+        12 => "private_thread",
+        13 => "stage",
+        14 => "directory",
+        15 => "forum",
+        16 => "media",
+        _ => "unknown",
+    }
+}
+
+/// Threads only visible to the members added to them, never started from a message.
+const PRIVATE_THREAD_KIND: u8 = 12;
+
+/// Forum and media channels, whose threads are posts opened by the thread's own first message.
+pub const FORUM_CHANNEL_KINDS: [u8; 2] = [
+    15, // GUILD_FORUM
+    16, // GUILD_MEDIA
+];
+
+/// Body of the active and archived threads endpoints, only the archived ones page with `has_more`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadList {
+    pub threads: Vec<Channel>,
+    #[serde(default)]
+    pub has_more: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DMChannel {
     pub id: String,
+    #[serde(default)]
+    pub name: Option<String>,
     pub last_message_id: Option<String>,
     pub recipients: Vec<User>,
     #[serde(rename = "type")]
     pub kind: u8,
 }
 
+/// Discord epoch (2015-01-01T00:00:00Z) in milliseconds, snowflakes encode their creation time relative to it.
+pub const DISCORD_EPOCH: i64 = 1_420_070_400_000;
+
+/// Smallest snowflake that could have been created at `date`, usable as a `before`/`after` bound.
+pub fn datetime_to_snowflake(date: &DateTime<Utc>) -> u64 {
+    ((date.timestamp_millis() - DISCORD_EPOCH).max(0) as u64) << 22
+}
+
+fn snowflake_value(id: &str) -> u64 {
+    id.parse::<u64>().unwrap_or_default()
+}
+
+/// Largest page of joined guilds Discord hands back.
+const JOINED_GUILDS_PAGE_SIZE: usize = 200;
+
 #[derive(Debug, Clone)]
 pub enum DiscordAPIRoutes {
     Users(String),
     Guilds(String),
     GuildChannels(String),
+    GuildActiveThreads(String),
+    Channels(String),
+    ArchivedThreads {
+        id: String,
+        private: bool,
+        before: Option<String>,
+    },
     Messages {
         id: String,
         limit: usize,
@@ -72,17 +310,47 @@ pub enum DiscordAPIRoutes {
         after: Option<String>,
         around: Option<String>,
     },
-    JoinedGuilds,
+    JoinedGuilds {
+        after: Option<String>,
+    },
     JoinedDMChannels,
 }
 
+/// Percent-encodes everything but unreserved characters, for query values that aren't plain ids.
+fn encode_query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
 impl DiscordAPIRoutes {
+    /// Path relative to the API base, query included.
     fn build(&self) -> String {
         match self {
-            DiscordAPIRoutes::Guilds(id) => format!("https://discord.com/api/v10/guilds/{}", id),
-            DiscordAPIRoutes::Users(id) => format!("https://discord.com/api/v10/users/{}", id),
-            DiscordAPIRoutes::GuildChannels(id) => {
-                format!("https://discord.com/api/v10/guilds/{}/channels", id)
+            DiscordAPIRoutes::Guilds(id) => format!("/guilds/{}", id),
+            DiscordAPIRoutes::Users(id) => format!("/users/{}", id),
+            DiscordAPIRoutes::GuildChannels(id) => format!("/guilds/{}/channels", id),
+            DiscordAPIRoutes::GuildActiveThreads(id) => format!("/guilds/{}/threads/active", id),
+            DiscordAPIRoutes::Channels(id) => format!("/channels/{}", id),
+            DiscordAPIRoutes::ArchivedThreads {
+                id,
+                private,
+                before,
+            } => {
+                let visibility = if *private { "private" } else { "public" };
+                let inital = format!("/channels/{}/threads/archived/{}?limit=100", id, visibility);
+
+                // `before` is an ISO 8601 timestamp here, its `+00:00` offset has to be escaped
+                match before {
+                    Some(bval) => format!("{}&before={}", inital, encode_query_value(bval)),
+                    None => inital,
+                }
             }
             DiscordAPIRoutes::Messages {
                 id,
@@ -91,10 +359,7 @@ impl DiscordAPIRoutes {
                 after,
                 around,
             } => {
-                let inital = format!(
-                    "https://discord.com/api/v10/channels/{}/messages?limit={}",
-                    id, limit
-                );
+                let inital = format!("/channels/{}/messages?limit={}", id, limit);
 
                 if let Some(bval) = before {
                     let final_string = format!("{}&before={}", inital, bval);
@@ -109,98 +374,365 @@ impl DiscordAPIRoutes {
                     return final_string;
                 }
 
-                return inital;
-            }
-            DiscordAPIRoutes::JoinedGuilds => {
-                "https://discord.com/api/v10/users/@me/guilds".to_string()
+                inital
             }
-            DiscordAPIRoutes::JoinedDMChannels => {
-                "https://discord.com/api/v10/users/@me/channels".to_string()
+            DiscordAPIRoutes::JoinedGuilds { after } => {
+                let inital = format!("/users/@me/guilds?limit={}", JOINED_GUILDS_PAGE_SIZE);
+
+                match after {
+                    Some(aval) => format!("{}&after={}", inital, aval),
+                    None => inital,
+                }
             }
+            DiscordAPIRoutes::JoinedDMChannels => "/users/@me/channels".to_string(),
         }
     }
 
-    async fn fetch(&self, headers: HeaderMap) -> Result<String> {
-        let url = self.build();
-        let client = Client::new();
-
-        let res = client
-            .get(url.clone())
-            .headers(headers)
-            .send()
-            .await?
-            .text()
-            .await?;
+    /// Identifies the route for rate limiting: the path without its query, ids kept since buckets are per channel or guild.
+    fn route(&self) -> String {
+        let path = self.build();
+        match path.split_once('?') {
+            Some((route, _)) => route.to_string(),
+            None => path,
+        }
+    }
 
-        Ok(res)
+    async fn fetch(&self, http: &DiscordHttp) -> Result<String> {
+        http.get(&self.route(), &self.build()).await
     }
 }
 
+const DISCORD_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whole request, body included. A page of 100 messages is well under this even on slow links.
+const DISCORD_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+const DISCORD_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
 #[derive(Debug)]
 pub struct DiscordClient {
-    _headers: HeaderMap,
+    http: DiscordHttp,
+    /// Same configuration without the credentials, for attachments on the CDN
+    media: Client,
+}
+
+/// Timeouts, compression and proxy shared by every client talking to Discord.
+fn client_builder(env: &DiscordEnvironment) -> Result<ClientBuilder> {
+    let mut builder = Client::builder()
+        .connect_timeout(DISCORD_CONNECT_TIMEOUT)
+        .timeout(DISCORD_REQUEST_TIMEOUT)
+        .pool_idle_timeout(DISCORD_POOL_IDLE_TIMEOUT)
+        .gzip(true)
+        .brotli(true)
+        .deflate(true)
+        .zstd(true);
+
+    if let Some(proxy) = &env.proxy {
+        builder = builder.proxy(Proxy::all(proxy)?);
+    }
+
+    Ok(builder)
 }
 
 impl DiscordClient {
-    pub async fn init(headers: HeaderMap) -> Result<Self> {
-        Ok(Self { _headers: headers })
+    /// Builds the one HTTP client every Discord request goes through, so connections and TLS
+    /// sessions are pooled across pages and channels.
+    pub async fn init(headers: HeaderMap, env: &DiscordEnvironment) -> Result<Self> {
+        Ok(Self {
+            http: DiscordHttp::new(
+                env.api_base.clone(),
+                client_builder(env)?.default_headers(headers).build()?,
+            ),
+            media: client_builder(env)?.build()?,
+        })
     }
 
-    pub async fn get_messages(
-        &self,
-        channel: String,
-        limit: usize,
-        progress: Option<ProgressBar>,
-    ) -> Result<Vec<Message>> {
-        let mut total_list: Vec<Message> = vec![];
-
-        if limit <= 100 {
-            let mut messages = from_str::<Vec<Message>>(
-                &DiscordAPIRoutes::Messages {
-                    id: channel.clone(),
-                    limit,
-                    before: None,
-                    after: None,
-                    around: None,
+    /// Client attachments are downloaded with, so they go through the same proxy and timeouts.
+    pub fn attachment_client(&self) -> Client {
+        self.media.clone()
+    }
+
+    /// Every guild the account is a member of.
+    pub async fn get_joined_guilds(&self) -> Result<Vec<PartialGuild>> {
+        let mut guilds: Vec<PartialGuild> = vec![];
+        let mut after: Option<String> = None;
+
+        loop {
+            let page = from_str::<Vec<PartialGuild>>(
+                &DiscordAPIRoutes::JoinedGuilds {
+                    after: after.clone(),
                 }
-                .fetch(self._headers.clone())
+                .fetch(&self.http)
                 .await?,
             )?;
+            let exhausted = page.len() < JOINED_GUILDS_PAGE_SIZE;
+
+            after = page.last().map(|g| g.id.clone());
+            guilds.extend(page);
 
-            if let Some(ref prog) = progress {
-                prog.inc(messages.len() as u64);
+            if exhausted {
+                break;
             }
+        }
 
-            total_list.append(&mut messages);
-        } else {
-            let mut before = None;
-
-            while total_list.len() < limit {
-                let msg_str = DiscordAPIRoutes::Messages {
-                    id: channel.clone(),
-                    limit: 100usize,
-                    before: before,
-                    after: None,
-                    around: None,
+        Ok(guilds)
+    }
+
+    /// Channels of a guild, categories and voice channels included. Threads are not listed here.
+    pub async fn get_guild_channels(&self, guild_id: String) -> Result<Vec<Channel>> {
+        Ok(from_str::<Vec<Channel>>(
+            &DiscordAPIRoutes::GuildChannels(guild_id)
+                .fetch(&self.http)
+                .await?,
+        )?)
+    }
+
+    /// Open direct message and group DM channels.
+    pub async fn get_dm_channels(&self) -> Result<Vec<DMChannel>> {
+        Ok(from_str::<Vec<DMChannel>>(
+            &DiscordAPIRoutes::JoinedDMChannels.fetch(&self.http).await?,
+        )?)
+    }
+
+    /// Every channel and active thread of a guild that holds messages, as dump targets tagged with the guild.
+    pub async fn get_guild_targets(&self, guild_id: String) -> Result<Vec<ResolvedPeer>> {
+        let guild = from_str::<PartialGuild>(
+            &DiscordAPIRoutes::Guilds(guild_id.clone())
+                .fetch(&self.http)
+                .await?,
+        )?;
+        let channels = self.get_guild_channels(guild_id.clone()).await?;
+        let threads = from_str::<ThreadList>(
+            &DiscordAPIRoutes::GuildActiveThreads(guild_id)
+                .fetch(&self.http)
+                .await?,
+        )?
+        .threads;
+
+        let parent = PeerParent::new(guild.id.clone(), guild.name.clone());
+
+        let mut targets = vec![];
+        for channel in channels.iter().chain(threads.iter()) {
+            if !MESSAGE_CHANNEL_KINDS.contains(&channel.kind) {
+                continue;
+            }
+
+            let thread = channel
+                .parent_id
+                .as_ref()
+                .filter(|_| channel.thread_metadata.is_some())
+                .and_then(|parent_id| channels.iter().find(|c| &c.id == parent_id))
+                .map(|parent_channel| Self::thread_origin(parent_channel, channel));
+
+            targets.push(
+                ResolvedPeer::new(channel.id.clone(), channel.id.clone())
+                    .with_name(channel.name.clone())
+                    .with_parent(Some(parent.clone()))
+                    .with_thread(thread),
+            );
+        }
+
+        Ok(targets)
+    }
+
+    /// Links a thread to the channel it was opened in and the message it was started from, which
+    /// shares the thread's id. Forum posts always have one, public and announcement threads are taken
+    /// to have one too, the id names no message for the few opened on their own or whose starter was
+    /// deleted. Private threads can't be started from a message.
+    fn thread_origin(parent: &Channel, thread: &Channel) -> ThreadOrigin {
+        let starter_message_id = (thread.kind != PRIVATE_THREAD_KIND).then(|| thread.id.clone());
+
+        ThreadOrigin::new(parent.id.clone(), parent.name.clone(), starter_message_id)
+    }
+
+    /// Archived threads of a channel, newest archived first. Private ones need Manage Threads and
+    /// are skipped when the account lacks it.
+    async fn get_archived_threads(&self, channel_id: &str, private: bool) -> Result<Vec<Channel>> {
+        let mut threads: Vec<Channel> = vec![];
+        let mut before: Option<String> = None;
+
+        loop {
+            let page = DiscordAPIRoutes::ArchivedThreads {
+                id: channel_id.to_string(),
+                private,
+                before: before.clone(),
+            }
+            .fetch(&self.http)
+            .await;
+
+            let page = match page {
+                Ok(page) => from_str::<ThreadList>(&page)?,
+                Err(e)
+                    if private
+                        && e.downcast_ref::<DiscordAPIError>()
+                            .is_some_and(|e| e.kind.is_target_specific()) =>
+                {
+                    break;
                 }
-                .fetch(self._headers.clone())
-                .await?;
+                Err(e) => return Err(e),
+            };
+
+            before = page
+                .threads
+                .last()
+                .and_then(|t| t.thread_metadata.as_ref())
+                .map(|m| m.archive_timestamp.clone());
+            threads.extend(page.threads);
+
+            if !page.has_more || before.is_none() {
+                break;
+            }
+        }
+
+        Ok(threads)
+    }
+
+    /// Active and archived threads (or forum posts) under a channel, as dump targets linked to the
+    /// channel and their starter message.
+    pub async fn get_thread_targets(
+        &self,
+        channel_id: String,
+        include_private: bool,
+    ) -> Result<Vec<ResolvedPeer>> {
+        let channel = from_str::<Channel>(
+            &DiscordAPIRoutes::Channels(channel_id.clone())
+                .fetch(&self.http)
+                .await?,
+        )?;
+
+        let mut threads: Vec<Channel> = vec![];
+        let mut guild: Option<PeerParent> = None;
+
+        if let Some(ref guild_id) = channel.guild_id {
+            let partial = from_str::<PartialGuild>(
+                &DiscordAPIRoutes::Guilds(guild_id.clone())
+                    .fetch(&self.http)
+                    .await?,
+            )?;
+            guild = Some(PeerParent::new(partial.id, partial.name));
+
+            let active = from_str::<ThreadList>(
+                &DiscordAPIRoutes::GuildActiveThreads(guild_id.clone())
+                    .fetch(&self.http)
+                    .await?,
+            )?;
+            threads.extend(
+                active
+                    .threads
+                    .into_iter()
+                    .filter(|t| t.parent_id.as_ref() == Some(&channel.id)),
+            );
+        }
+
+        threads.extend(self.get_archived_threads(&channel.id, false).await?);
+        if include_private {
+            threads.extend(self.get_archived_threads(&channel.id, true).await?);
+        }
+
+        // A thread archived or unarchived while listing shows up in both lists
+        let mut seen = HashSet::new();
+        threads.retain(|t| seen.insert(t.id.clone()));
+
+        let mut targets = vec![];
+        for thread in threads.iter() {
+            targets.push(
+                ResolvedPeer::new(thread.id.clone(), thread.id.clone())
+                    .with_name(thread.name.clone())
+                    .with_parent(guild.clone())
+                    .with_thread(Some(Self::thread_origin(&channel, thread))),
+            );
+        }
+
+        Ok(targets)
+    }
+
+    /// Pages through a channel's history. By default walks backwards from the newest message and
+    /// stops at `date_point`, when `reverse` is set walks forward from `date_point` to the present.
+    pub async fn get_messages(
+        &self,
+        channel: String,
+        options: &DumpOptions,
+        sink: &mut PeerSink,
+        progress: Option<ProgressBar>,
+    ) -> Result<()> {
+        let limit = match options.limit {
+            Some(lim) => lim as usize,
+            None => 1e+8 as usize,
+        };
+
+        sink.begin(options.resume).await?;
+
+        if let Some(ref prog) = progress {
+            prog.inc(sink.fetched() as u64);
+        }
+
+        let date_point_snowflake = datetime_to_snowflake(&options.date_point);
+
+        // A restored checkpoint moves the cursor past everything collected by the interrupted run
+        let cursor = sink.cursor().cloned();
+        let mut before: Option<String> = None;
+        let mut after: Option<String> = None;
+        if options.reverse {
+            after = cursor.or(Some(date_point_snowflake.to_string()));
+        } else {
+            before = cursor;
+        }
+
+        'page_loop: while sink.fetched() < limit {
+            let page_size = (limit - sink.fetched()).min(100usize);
+
+            let msg_str = DiscordAPIRoutes::Messages {
+                id: channel.clone(),
+                limit: page_size,
+                before: before.clone(),
+                after: after.clone(),
+                around: None,
+            }
+            .fetch(&self.http)
+            .await?;
 
-                let mut messages = from_str::<Vec<Message>>(&msg_str)?;
+            let mut messages = from_str::<Vec<Message>>(&msg_str)?;
+            let exhausted = messages.len() < page_size;
+
+            if options.reverse {
+                // Pages requested with `after` hold the oldest messages past the bound, keep them oldest first
+                messages.sort_by_key(|m| snowflake_value(&m.id));
+                after = messages.last().map(|m| m.id.clone());
+            } else {
+                before = messages.last().map(|m| m.id.clone());
+            }
+
+            let mut page: Vec<PeerMessage> = vec![];
+            let mut reached_date_point = false;
+
+            for message in messages.iter() {
+                if !options.reverse && snowflake_value(&message.id) < date_point_snowflake {
+                    reached_date_point = true;
+                    break;
+                }
 
                 if let Some(ref prog) = progress {
-                    prog.inc(messages.len() as u64);
+                    prog.inc(1);
                 }
 
-                if messages.len() < 100usize {
-                    total_list.append(&mut messages);
-                    break;
+                let mut peer_message = PeerMessage::try_from(message)?;
+                if let Some(media) = sink.media() {
+                    for (index, attachment) in peer_message.attachments.iter_mut().enumerate() {
+                        if media.wants(attachment) {
+                            media
+                                .download_url(&channel, &message.id, index, attachment)
+                                .await;
+                        }
+                    }
                 }
 
-                before = Some(messages[messages.len() - 1usize].id.clone());
-                total_list.append(&mut messages);
+                page.push(peer_message);
+            }
+
+            sink.write_page(page).await?;
 
-                sleep(Duration::from_millis(250)).await;
+            if exhausted || reached_date_point {
+                break 'page_loop;
             }
         }
 
@@ -208,6 +740,102 @@ impl DiscordClient {
             prog.finish_with_message(format!("{} - Dumped", channel.clone()));
         }
 
-        Ok(total_list)
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archived_threads_before_is_encoded() {
+        let path = DiscordAPIRoutes::ArchivedThreads {
+            id: "1".to_string(),
+            private: false,
+            before: Some("2024-10-01T00:00:00.123000+00:00".to_string()),
+        }
+        .build();
+
+        assert_eq!(
+            path,
+            "/channels/1/threads/archived/public?limit=100&before=2024-10-01T00%3A00%3A00.123000%2B00%3A00"
+        );
+    }
+
+    fn message(timestamp: &str) -> Message {
+        from_str(&format!(
+            r#"{{"id": "2", "channel_id": "1", "author": {{"id": "9", "username": "u", "discriminator": "0", "avatar": null}}, "content": "m", "timestamp": "{}"}}"#,
+            timestamp
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn message_timestamps_are_parsed() {
+        let peer_message =
+            PeerMessage::try_from(&message("2024-10-01T00:00:00.123000+00:00")).unwrap();
+
+        assert_eq!(
+            peer_message.date.to_rfc3339(),
+            "2024-10-01T00:00:00.123+00:00"
+        );
+    }
+
+    #[test]
+    fn malformed_message_timestamps_are_errors() {
+        for timestamp in ["", "yesterday"] {
+            let error = PeerMessage::try_from(&message(timestamp)).unwrap_err();
+
+            assert!(error.downcast_ref::<DateTimeParseError>().is_some());
+        }
+    }
+
+    fn channel(id: &str, kind: u8, parent_id: Option<&str>) -> Channel {
+        Channel {
+            id: id.to_string(),
+            guild_id: Some("7".to_string()),
+            name: Some(format!("channel {}", id)),
+            kind,
+            parent_id: parent_id.map(str::to_string),
+            thread_metadata: None,
+        }
+    }
+
+    #[test]
+    fn text_channel_threads_link_their_starter_message() {
+        let parent = channel("1", 0, None);
+        let origin = DiscordClient::thread_origin(&parent, &channel("2", 11, Some("1")));
+
+        assert_eq!(origin.channel_id, "1");
+        assert_eq!(origin.channel_name.as_deref(), Some("channel 1"));
+        assert_eq!(origin.starter_message_id.as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn announcement_threads_and_forum_posts_link_their_starter_message() {
+        let announcement = channel("1", 5, None);
+        let forum = channel("3", 15, None);
+
+        assert_eq!(
+            DiscordClient::thread_origin(&announcement, &channel("2", 10, Some("1")))
+                .starter_message_id
+                .as_deref(),
+            Some("2")
+        );
+        assert_eq!(
+            DiscordClient::thread_origin(&forum, &channel("4", 11, Some("3")))
+                .starter_message_id
+                .as_deref(),
+            Some("4")
+        );
+    }
+
+    #[test]
+    fn private_threads_have_no_starter_message() {
+        let parent = channel("1", 0, None);
+        let origin = DiscordClient::thread_origin(&parent, &channel("2", 12, Some("1")));
+
+        assert_eq!(origin.starter_message_id, None);
     }
 }
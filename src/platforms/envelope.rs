@@ -0,0 +1,118 @@
+use std::env;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, from_value, to_value};
+
+use crate::platforms::structs::{DumpOptions, DumpedPeer};
+
+/// Format written by this build. Version 1 is the bare `DumpedPeer` / `Vec<DumpedPeer>` files
+/// written before dumps carried an envelope.
+pub const DUMP_FORMAT_VERSION: u32 = 2;
+
+/// Describes the run a dump came from. Leads every JSON dump and is the first line of a JSON lines dump.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DumpMetadata {
+    pub format_version: u32,
+    pub tool_version: String,
+    pub platform: String,
+    pub arguments: Vec<String>,
+    pub execution_time: DateTime<Utc>,
+    pub date_point: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Version the file was upgraded from by `migrate`, absent on files written as they are
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub migrated_from: Option<u32>,
+}
+
+impl DumpMetadata {
+    pub fn new(platform: &str, options: &DumpOptions, execution_time: DateTime<Utc>) -> Self {
+        Self {
+            format_version: DUMP_FORMAT_VERSION,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            platform: platform.to_string(),
+            arguments: env::args().collect(),
+            execution_time,
+            date_point: Some(options.date_point),
+            until: options.until,
+            migrated_from: None,
+        }
+    }
+
+    /// Stand-in for files written before metadata was recorded, nothing is known beyond the platform and file time.
+    pub fn legacy(platform: String, execution_time: DateTime<Utc>) -> Self {
+        Self {
+            format_version: DUMP_FORMAT_VERSION,
+            tool_version: "unknown".to_string(),
+            platform,
+            arguments: vec![],
+            execution_time,
+            date_point: None,
+            until: None,
+            migrated_from: Some(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DumpEnvelope {
+    #[serde(flatten)]
+    pub metadata: DumpMetadata,
+    pub peers: Vec<DumpedPeer>,
+}
+
+impl DumpEnvelope {
+    pub fn new(metadata: DumpMetadata, peers: Vec<DumpedPeer>) -> Self {
+        Self { metadata, peers }
+    }
+}
+
+/// Version a parsed dump document was written with, bare documents predate the field.
+pub fn document_version(document: &Value) -> u32 {
+    document
+        .get("format_version")
+        .and_then(Value::as_u64)
+        .map(|version| version as u32)
+        .unwrap_or(1)
+}
+
+/// Steps a parsed dump document up one version at a time until it matches `DUMP_FORMAT_VERSION`.
+/// `legacy` supplies the metadata for files that never had any.
+pub fn upgrade_document(
+    mut document: Value,
+    legacy: impl FnOnce() -> DumpMetadata,
+) -> Result<DumpEnvelope, String> {
+    let mut version = document_version(&document);
+
+    if version > DUMP_FORMAT_VERSION {
+        return Err(format!(
+            "format version {} is newer than the supported version {}, update the tool",
+            version, DUMP_FORMAT_VERSION
+        ));
+    }
+
+    let mut legacy = Some(legacy);
+
+    while version < DUMP_FORMAT_VERSION {
+        document = match version {
+            1 => upgrade_from_bare(document, legacy.take().unwrap()())?,
+            _ => unreachable!(),
+        };
+        version += 1;
+    }
+
+    from_value::<DumpEnvelope>(document).map_err(|e| e.to_string())
+}
+
+/// Version 1 to 2: wraps a bare peer, or list of peers, in an envelope.
+fn upgrade_from_bare(document: Value, metadata: DumpMetadata) -> Result<Value, String> {
+    let peers = match document {
+        Value::Array(peers) => peers,
+        peer => vec![peer],
+    };
+
+    let mut envelope = to_value(DumpEnvelope::new(metadata, vec![])).map_err(|e| e.to_string())?;
+    envelope["peers"] = Value::Array(peers);
+
+    Ok(envelope)
+}
@@ -0,0 +1,317 @@
+use std::{
+    fs::File,
+    io::copy,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::Result;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::{OpenOptions, create_dir_all, metadata, remove_file, rename, try_exists},
+    io::AsyncWriteExt,
+    task::spawn_blocking,
+};
+
+use crate::{
+    platforms::structs::{Attachment, AttachmentKind},
+    utils::{append_json_lines, error_reason},
+};
+
+pub const MEDIA_MANIFEST: &str = "manifest.jsonl";
+
+/// Which attachments a run downloads, from the `--media-*` arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaOptions {
+    pub directory: PathBuf,
+    pub max_file_size: Option<u64>,
+    /// Empty downloads every kind
+    pub kinds: Vec<AttachmentKind>,
+    pub budget: Option<u64>,
+}
+
+impl MediaOptions {
+    pub fn new(
+        directory: PathBuf,
+        max_file_size: Option<u64>,
+        kinds: Vec<AttachmentKind>,
+        budget: Option<u64>,
+    ) -> Self {
+        Self {
+            directory,
+            max_file_size,
+            kinds,
+            budget,
+        }
+    }
+}
+
+/// Links a message's attachment to the stored file, one line of the manifest per download.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaRecord {
+    pub platform: String,
+    pub peer_id: String,
+    pub message_id: String,
+    pub kind: AttachmentKind,
+    pub filename: Option<String>,
+    pub mime_type: Option<String>,
+    pub size: u64,
+    pub sha256: String,
+    /// Relative to the media directory
+    pub path: String,
+    pub source: Option<String>,
+    pub downloaded_at: DateTime<Utc>,
+}
+
+/// Content addressed file store shared by every target of a run. Files live under
+/// `<directory>/<first two hash characters>/<sha256>.<extension>`, so the same file attached twice is
+/// stored once, and `manifest.jsonl` records which message each came from.
+#[derive(Debug, Clone)]
+pub struct MediaStore {
+    options: Arc<MediaOptions>,
+    platform: String,
+    /// Only platforms serving attachments over plain URLs hand one over
+    client: Option<Client>,
+    spent: Arc<AtomicU64>,
+}
+
+impl MediaStore {
+    pub async fn open(
+        options: MediaOptions,
+        platform: &str,
+        client: Option<Client>,
+    ) -> Result<Self> {
+        create_dir_all(options.directory.join("tmp")).await?;
+
+        Ok(Self {
+            options: Arc::new(options),
+            platform: platform.to_string(),
+            client,
+            spent: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// Whether the kind and size filters let `attachment` through. Attachments of unknown size are
+    /// admitted and checked once downloaded.
+    pub fn wants(&self, attachment: &Attachment) -> bool {
+        let kind_allowed =
+            self.options.kinds.is_empty() || self.options.kinds.contains(&attachment.kind);
+        let size_allowed = match (attachment.size, self.options.max_file_size) {
+            (Some(size), Some(max)) => size <= max,
+            _ => true,
+        };
+
+        kind_allowed && size_allowed && !self.budget_exhausted(attachment.size.unwrap_or(0))
+    }
+
+    fn budget_exhausted(&self, size: u64) -> bool {
+        match self.options.budget {
+            Some(budget) => self.spent.load(Ordering::SeqCst) + size > budget,
+            None => false,
+        }
+    }
+
+    /// Fresh file to download into before the content hash is known. Message ids are only unique
+    /// within a peer on Telegram, so the peer is part of the name.
+    fn temporary_path(&self, peer_id: &str, message_id: &str, index: usize) -> PathBuf {
+        self.options.directory.join("tmp").join(format!(
+            "{}_{}_{}_{}_{}",
+            self.platform,
+            peer_id,
+            message_id,
+            index,
+            Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ))
+    }
+
+    /// Downloads an attachment through `fetch`, which writes it to the path it is given and tells
+    /// whether there was anything to write. A failed download is recorded on the attachment rather
+    /// than failing the dump, and leaves no temporary file behind.
+    pub async fn download<F>(
+        &self,
+        peer_id: &str,
+        message_id: &str,
+        index: usize,
+        attachment: &mut Attachment,
+        fetch: F,
+    ) where
+        F: AsyncFnOnce(PathBuf) -> Result<bool>,
+    {
+        let temporary = self.temporary_path(peer_id, message_id, index);
+
+        let stored = match fetch(temporary.clone()).await {
+            Ok(true) => {
+                self.store(&temporary, peer_id, message_id, attachment)
+                    .await
+            }
+            Ok(false) => Ok(()),
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = stored {
+            attachment.download_error = Some(error_reason(&e));
+        }
+
+        if try_exists(&temporary).await.unwrap_or(false) {
+            remove_file(&temporary).await.ok();
+        }
+    }
+
+    /// Downloads an attachment served over plain HTTP, e.g. from Discord's CDN.
+    pub async fn download_url(
+        &self,
+        peer_id: &str,
+        message_id: &str,
+        index: usize,
+        attachment: &mut Attachment,
+    ) {
+        let (Some(client), Some(url)) = (&self.client, attachment.url.clone()) else {
+            return;
+        };
+
+        self.download(peer_id, message_id, index, attachment, async |temporary| {
+            // Expired or deleted files fail here and are recorded like any other failed download
+            let mut response = client.get(url).send().await?.error_for_status()?;
+
+            let mut file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(&temporary)
+                .await?;
+
+            while let Some(chunk) = response.chunk().await? {
+                file.write_all(&chunk).await?;
+            }
+            file.flush().await?;
+
+            Ok(true)
+        })
+        .await
+    }
+
+    /// Takes `size` out of the budget unless that would overrun it. Targets download concurrently,
+    /// so checking and spending the budget happen in one step.
+    fn reserve(&self, size: u64) -> bool {
+        self.spent
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |spent| {
+                match self.options.budget {
+                    Some(budget) if spent + size > budget => None,
+                    _ => Some(spent + size),
+                }
+            })
+            .is_ok()
+    }
+
+    /// Moves a finished download to its content addressed path and records it in the manifest.
+    /// Downloads past the size limit or budget are dropped.
+    async fn store(
+        &self,
+        temporary: &Path,
+        peer_id: &str,
+        message_id: &str,
+        attachment: &mut Attachment,
+    ) -> Result<()> {
+        let size = metadata(temporary).await?.len();
+
+        let over_limit = self.options.max_file_size.is_some_and(|max| size > max);
+        if over_limit || !self.reserve(size) {
+            remove_file(temporary).await?;
+            return Ok(());
+        }
+
+        let placed = self
+            .place(temporary, size, peer_id, message_id, attachment)
+            .await;
+        if placed.is_err() {
+            self.spent.fetch_sub(size, Ordering::SeqCst);
+        }
+
+        placed
+    }
+
+    async fn place(
+        &self,
+        temporary: &Path,
+        size: u64,
+        peer_id: &str,
+        message_id: &str,
+        attachment: &mut Attachment,
+    ) -> Result<()> {
+        let sha256 = {
+            let temporary = temporary.to_path_buf();
+            spawn_blocking(move || -> Result<String> {
+                let mut hasher = Sha256::new();
+                copy(&mut File::open(temporary)?, &mut hasher)?;
+                Ok(format!("{:x}", hasher.finalize()))
+            })
+            .await??
+        };
+
+        let extension = attachment
+            .filename
+            .as_deref()
+            .and_then(|name| Path::new(name).extension())
+            .map(|ext| format!(".{}", ext.to_string_lossy().to_lowercase()))
+            .unwrap_or_default();
+        let relative = PathBuf::from(&sha256[..2]).join(format!("{}{}", sha256, extension));
+        let destination = self.options.directory.join(&relative);
+
+        if try_exists(&destination).await? {
+            remove_file(temporary).await?;
+        } else {
+            create_dir_all(destination.parent().unwrap()).await?;
+            rename(temporary, &destination).await?;
+        }
+
+        attachment.sha256 = Some(sha256.clone());
+
+        let record = MediaRecord {
+            platform: self.platform.clone(),
+            peer_id: peer_id.to_string(),
+            message_id: message_id.to_string(),
+            kind: attachment.kind,
+            filename: attachment.filename.clone(),
+            mime_type: attachment.mime_type.clone(),
+            size,
+            sha256,
+            path: relative.to_string_lossy().to_string(),
+            source: attachment.url.clone().or(attachment.file_id.clone()),
+            downloaded_at: Utc::now(),
+        };
+
+        append_json_lines(&self.options.directory.join(MEDIA_MANIFEST), &[record]).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temporary_paths_of_different_peers_differ() {
+        let store = MediaStore {
+            options: Arc::new(MediaOptions::new(
+                PathBuf::from("media"),
+                None,
+                vec![],
+                None,
+            )),
+            platform: "telegram".to_string(),
+            client: None,
+            spent: Arc::new(AtomicU64::new(0)),
+        };
+
+        let first = store.temporary_path("100", "5", 0);
+        let second = store.temporary_path("200", "5", 0);
+
+        assert_ne!(first, second);
+        assert!(first.starts_with("media/tmp"));
+    }
+}
@@ -1,3 +1,11 @@
+pub mod checkpoint;
+pub mod columnar;
 pub mod discord;
+pub mod envelope;
+pub mod media;
+pub mod platform;
+pub mod runner;
+pub mod sink;
+pub mod sqlite;
 pub mod structs;
 pub mod telegram;
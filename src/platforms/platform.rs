@@ -0,0 +1,56 @@
+use std::{future::Future, path::Path, sync::Arc};
+
+use color_eyre::eyre::{Report, Result};
+use indicatif::ProgressBar;
+use reqwest::Client;
+
+use crate::platforms::{
+    sink::PeerSink,
+    structs::{DumpOptions, ResolvedPeer},
+};
+
+/// A messaging platform the generic runner can drive: list the targets of a run, resolve each to the
+/// peer recorded in outputs and dump its history into a sink.
+pub trait Platform: Sized + Send + Sync + 'static {
+    /// What a dump is pointed at, e.g. a resolved Telegram peer or a Discord channel id.
+    type Target: Clone + Send + Sync + 'static;
+
+    /// Prefix of output, checkpoint and store names.
+    const NAME: &'static str;
+
+    /// Targets dumped at the same time.
+    const CONCURRENCY: usize;
+
+    /// JSON dumps write every target of a run into one document instead of a file per target.
+    const SINGLE_JSON_DOCUMENT: bool;
+
+    /// Shared client, initialized on first use.
+    fn client() -> impl Future<Output = Result<Arc<Self>>> + Send;
+
+    /// Reads the targets file given on the command line.
+    fn list(targets_file: &Path) -> impl Future<Output = Result<Vec<Self::Target>>> + Send;
+
+    /// Short name shown on progress bars before the target is resolved.
+    fn label(target: &Self::Target) -> String;
+
+    /// HTTP client for attachments served from plain URLs, configured like the platform's own requests.
+    fn media_client(&self) -> Option<Client> {
+        None
+    }
+
+    fn resolve(&self, target: &Self::Target) -> impl Future<Output = Result<ResolvedPeer>> + Send;
+
+    /// Explains `error` when it only concerns the target it came from, the runner then reports the
+    /// target as failed and carries on with the others. Anything else aborts the run.
+    fn target_failure(_error: &Report) -> Option<String> {
+        None
+    }
+
+    fn dump(
+        &self,
+        target: &Self::Target,
+        options: &DumpOptions,
+        sink: &mut PeerSink,
+        progress: Option<ProgressBar>,
+    ) -> impl Future<Output = Result<()>> + Send;
+}
@@ -0,0 +1,415 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Report, Result, eyre};
+use futures::{FutureExt, future::try_join_all};
+use indicatif::ProgressBar;
+use serde_json::to_string;
+use tokio::{fs::write, sync::Semaphore};
+
+use crate::{
+    error::{BatchFailureError, SyncStartError},
+    export::read_dump_file,
+    platforms::{
+        checkpoint::{CheckpointStore, DumpWindow, remove_checkpoint_directory},
+        envelope::{DumpEnvelope, DumpMetadata},
+        media::{MediaOptions, MediaStore},
+        platform::Platform,
+        sink::{PeerOutput, PeerSink, RunSink, SinkKind},
+        structs::{DumpOptions, DumpedPeer, TargetFailure},
+    },
+    utils::file_timestamp,
+    visual::new_multi_progress,
+};
+
+/// What every target of a dump run writes into, opened once before the first target starts.
+#[derive(Debug, Clone)]
+struct DumpRun {
+    sink: RunSink,
+    metadata: DumpMetadata,
+    media: Option<MediaStore>,
+}
+
+async fn dump_single_target<P: Platform>(
+    semaphore: Arc<Semaphore>,
+    client: Arc<P>,
+    target: P::Target,
+    options: DumpOptions,
+    run: DumpRun,
+    progress: Option<ProgressBar>,
+) -> Result<Option<PeerSink>> {
+    let label = P::label(&target);
+
+    if let Some(ref prog) = progress
+        && semaphore.available_permits() == 0usize
+    {
+        prog.set_message(format!("{} - Awaiting permit...", label));
+    }
+
+    let permit = semaphore.acquire().await?;
+
+    if let Some(ref prog) = progress {
+        prog.set_message(label);
+    }
+
+    let peer = client.resolve(&target).await?;
+
+    let actual_limit = if let Some(lim) = options.limit {
+        format!("_{}", lim)
+    } else {
+        "".to_string()
+    };
+
+    let output_name = format!(
+        "{}_{}{}_{}",
+        P::NAME,
+        peer.peer_username,
+        actual_limit,
+        file_timestamp(&run.metadata.execution_time)
+    );
+
+    let mut sink = PeerSink::new(
+        peer.clone(),
+        CheckpointStore::new(P::NAME, &peer.peer_username, DumpWindow::from(&options)),
+        run.sink.peer_output(&output_name),
+    )
+    .with_media(run.media);
+
+    client
+        .dump(&target, &options, &mut sink, progress.clone())
+        .await?;
+
+    drop(permit);
+
+    // Peers of a run wide document are written along with every other once all targets are done
+    if P::SINGLE_JSON_DOCUMENT && matches!(run.sink, RunSink::Json) {
+        return Ok(Some(sink));
+    }
+
+    if let Some(dumped_peer) = sink.finish() {
+        let envelope = DumpEnvelope::new(run.metadata, vec![dumped_peer]);
+        write(format!("{}.json", output_name), to_string(&envelope)?).await?;
+    }
+
+    sink.clear().await?;
+
+    Ok(None)
+}
+
+/// Writes the peers held back by `dump_single_target` into `<platform>_dumped_peers_<count>_<time>.json`,
+/// then drops their checkpoints.
+async fn write_single_document<P: Platform>(
+    metadata: &DumpMetadata,
+    mut sinks: Vec<PeerSink>,
+) -> Result<()> {
+    if sinks.is_empty() {
+        return Ok(());
+    }
+
+    let peers = sinks
+        .iter_mut()
+        .filter_map(|sink| sink.finish())
+        .collect::<Vec<DumpedPeer>>();
+
+    let output = format!(
+        "{}_dumped_peers_{}_{}.json",
+        P::NAME,
+        peers.len(),
+        file_timestamp(&metadata.execution_time)
+    );
+    write(
+        output,
+        to_string(&DumpEnvelope::new(metadata.clone(), peers))?,
+    )
+    .await?;
+
+    for sink in sinks.iter() {
+        sink.clear().await?;
+    }
+
+    Ok(())
+}
+
+/// Marks a failed target's progress bar and records why it failed for the run's report, when the
+/// error only concerns that target. Any other error is handed back to abort the run.
+fn target_failed<P: Platform>(
+    label: String,
+    error: Report,
+    progress: &ProgressBar,
+) -> Result<TargetFailure> {
+    let Some(reason) = P::target_failure(&error) else {
+        return Err(error);
+    };
+    progress.abandon_with_message(format!("{} - Failed: {}", label, reason));
+
+    Ok(TargetFailure::new(label, reason))
+}
+
+/// Writes the failures of a batch to `<name>_failures_<count>_<time>.json`. The batch itself still
+/// succeeds unless `fail_on_error` is set.
+pub async fn report_failures(
+    name: &str,
+    failures: Vec<TargetFailure>,
+    total: usize,
+    execution_time: &DateTime<Utc>,
+    fail_on_error: bool,
+) -> Result<()> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+
+    let report = format!(
+        "{}_failures_{}_{}.json",
+        name,
+        failures.len(),
+        file_timestamp(execution_time)
+    );
+    write(&report, to_string(&failures)?).await?;
+
+    let error = BatchFailureError::new(failures.len(), total, report);
+    if fail_on_error {
+        return Err(eyre!(error));
+    }
+
+    eprintln!("{}", error);
+
+    Ok(())
+}
+
+/// Options every sync walks forward with, the date point only matters to targets starting from neither a store nor a seed.
+fn sync_options(date_point: Option<DateTime<Utc>>) -> DumpOptions {
+    DumpOptions::new(None, date_point.unwrap_or_default(), None, true, true)
+}
+
+/// What every target of a sync run starts from and writes into.
+#[derive(Debug, Clone)]
+struct SyncRun {
+    date_point: Option<DateTime<Utc>>,
+    store_directory: PathBuf,
+    seeds: Arc<HashMap<String, String>>,
+    metadata: DumpMetadata,
+}
+
+async fn sync_single_target<P: Platform>(
+    semaphore: Arc<Semaphore>,
+    client: Arc<P>,
+    target: P::Target,
+    run: SyncRun,
+    progress: Option<ProgressBar>,
+) -> Result<()> {
+    let permit = semaphore.acquire().await?;
+
+    if let Some(ref prog) = progress {
+        prog.set_message(P::label(&target));
+    }
+
+    let peer = client.resolve(&target).await?;
+
+    // Only the store's state is read, its checkpoint already holds the newest message synced
+    let store = CheckpointStore::with_directory(
+        &run.store_directory,
+        P::NAME,
+        &peer.peer_username,
+        DumpWindow::sync(),
+    );
+    let seed = run.seeds.get(&peer.peer_id).cloned();
+
+    if run.date_point.is_none() && seed.is_none() && store.load().await?.is_none() {
+        return Err(eyre!(SyncStartError::new(P::label(&target))));
+    }
+
+    let output = PeerOutput::Store(store.store_path(), run.metadata);
+    let mut sink = PeerSink::new(peer, store, output).with_seed(seed);
+
+    client
+        .dump(&target, &sync_options(run.date_point), &mut sink, progress)
+        .await?;
+
+    drop(permit);
+
+    Ok(())
+}
+
+/// Newest message of every peer found in `dumps`, by peer id, for targets to sync on from.
+async fn read_seeds(dumps: &[PathBuf]) -> Result<HashMap<String, String>> {
+    let mut newest: HashMap<String, (DateTime<Utc>, String)> = HashMap::new();
+
+    for dump in dumps {
+        for dumped_peer in read_dump_file(dump).await?.peers {
+            let Some(last) = dumped_peer.chunks.iter().max_by_key(|m| m.date) else {
+                continue;
+            };
+
+            let seed = newest
+                .entry(dumped_peer.peer.peer_id)
+                .or_insert_with(|| (last.date, last.id.clone()));
+            if last.date > seed.0 {
+                *seed = (last.date, last.id.clone());
+            }
+        }
+    }
+
+    Ok(newest
+        .into_iter()
+        .map(|(peer_id, (_, message_id))| (peer_id, message_id))
+        .collect())
+}
+
+/// Dumps every target listed in `targets_file`, writing each through the chosen sink.
+pub async fn run_dump<P: Platform>(
+    targets_file: PathBuf,
+    options: DumpOptions,
+    sink_kind: SinkKind,
+    media: Option<MediaOptions>,
+    fail_on_error: bool,
+) -> Result<()> {
+    let targets = P::list(&targets_file).await?;
+
+    run_dump_targets::<P>(targets, options, sink_kind, media, fail_on_error).await
+}
+
+/// Same as `run_dump`, for targets gathered some other way than a targets file.
+pub async fn run_dump_targets<P: Platform>(
+    targets: Vec<P::Target>,
+    options: DumpOptions,
+    sink_kind: SinkKind,
+    media: Option<MediaOptions>,
+    fail_on_error: bool,
+) -> Result<()> {
+    let metadata = DumpMetadata::new(P::NAME, &options, Utc::now());
+
+    let client = P::client().await?;
+
+    let media = match media {
+        Some(media) => Some(MediaStore::open(media, P::NAME, client.media_client()).await?),
+        None => None,
+    };
+
+    let (multiprog, style) = new_multi_progress()?;
+
+    let implied_limit = match options.limit {
+        Some(lim) => lim as u64,
+        None => 1e+8 as u64,
+    };
+
+    let run = DumpRun {
+        sink: RunSink::open(&sink_kind, &metadata).await?,
+        metadata,
+        media,
+    };
+
+    let semaphore = Arc::new(Semaphore::new(P::CONCURRENCY));
+
+    let futures = targets
+        .iter()
+        .map(|t| {
+            let progress = multiprog.add(ProgressBar::new(implied_limit));
+            progress.set_style(style.clone());
+            progress.set_message(format!("{} - Awaiting to start", P::label(t)));
+            let label = P::label(t);
+            dump_single_target(
+                semaphore.clone(),
+                client.clone(),
+                t.clone(),
+                options,
+                run.clone(),
+                Some(progress.clone()),
+            )
+            .map(move |dumped| match dumped {
+                Ok(sink) => Ok(Ok(sink)),
+                Err(error) => target_failed::<P>(label, error, &progress).map(Err),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // Targets failing on their own are collected for the report, anything else cancels the rest like sync does
+    let mut held_back: Vec<PeerSink> = vec![];
+    let mut failures: Vec<TargetFailure> = vec![];
+    for dumped in try_join_all(futures).await? {
+        match dumped {
+            Ok(Some(sink)) => held_back.push(sink),
+            Ok(None) => {}
+            Err(failure) => failures.push(failure),
+        }
+    }
+
+    write_single_document::<P>(&run.metadata, held_back).await?;
+
+    run.sink.finish().await?;
+
+    // Targets that failed keep their checkpoints, and with them the directory
+    if options.resume {
+        remove_checkpoint_directory().await?;
+    }
+
+    report_failures(
+        &format!("{}_dump", P::NAME),
+        failures,
+        targets.len(),
+        &run.metadata.execution_time,
+        fail_on_error,
+    )
+    .await
+}
+
+/// Fetches only what is newer than each target's store in `store_directory`, appending it there.
+/// Targets with nothing stored yet start after their newest message in the `seeds` dumps, or else from `date_point`.
+pub async fn run_sync<P: Platform>(
+    targets_file: PathBuf,
+    store_directory: PathBuf,
+    date_point: Option<DateTime<Utc>>,
+    seeds: Vec<PathBuf>,
+    fail_on_error: bool,
+) -> Result<()> {
+    let targets = P::list(&targets_file).await?;
+
+    let run = SyncRun {
+        date_point,
+        store_directory,
+        seeds: Arc::new(read_seeds(&seeds).await?),
+        // Recorded at the top of the stores this run creates
+        metadata: DumpMetadata::new(P::NAME, &sync_options(date_point), Utc::now()),
+    };
+
+    let client = P::client().await?;
+
+    let (multiprog, style) = new_multi_progress()?;
+
+    let semaphore = Arc::new(Semaphore::new(P::CONCURRENCY));
+
+    let futures = targets
+        .iter()
+        .map(|t| {
+            let progress = multiprog.add(ProgressBar::no_length());
+            progress.set_style(style.clone());
+            progress.set_message(format!("{} - Awaiting to start", P::label(t)));
+            let label = P::label(t);
+            sync_single_target(
+                semaphore.clone(),
+                client.clone(),
+                t.clone(),
+                run.clone(),
+                Some(progress.clone()),
+            )
+            .map(move |synced| match synced {
+                Ok(()) => Ok(None),
+                Err(error) => target_failed::<P>(label, error, &progress).map(Some),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let failures = try_join_all(futures)
+        .await?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<TargetFailure>>();
+
+    report_failures(
+        &format!("{}_sync", P::NAME),
+        failures,
+        targets.len(),
+        &run.metadata.execution_time,
+        fail_on_error,
+    )
+    .await
+}
@@ -0,0 +1,394 @@
+use std::{ffi::OsStr, path::PathBuf, str::FromStr};
+
+use clap::{
+    Arg, Command,
+    builder::{PossibleValue, TypedValueParser, ValueParserFactory},
+    error::{Error as ClapError, ErrorKind},
+};
+use color_eyre::eyre::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::to_string;
+use tokio::fs::{metadata, try_exists, write};
+
+use crate::{
+    platforms::{
+        checkpoint::{Checkpoint, CheckpointStore, truncate_output},
+        columnar::ParquetStore,
+        envelope::DumpMetadata,
+        media::MediaStore,
+        sqlite::SqliteStore,
+        structs::{DumpedPeer, PeerMessage, ResolvedPeer},
+    },
+    utils::append_json_lines,
+};
+
+/// Where dumped messages end up, chosen with `--sink`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SinkKind {
+    /// A single JSON document, built in memory and written once every page is in
+    Json,
+    /// A JSON lines file per target: the run metadata, a header record for the peer, then a line per message as it is fetched
+    JsonLines,
+    /// Normalized peers, messages and runs tables in a SQLite database, given as `sqlite:path.db`
+    Sqlite(PathBuf),
+    /// A single Parquet file with a row per message, given as `parquet:path.parquet`
+    Parquet(PathBuf),
+}
+
+/// Every form `--sink` takes along with what it writes, the single list behind its help and errors.
+const SINK_VALUES: [(&str, &str); 4] = [
+    (
+        "json",
+        "A single JSON document, written once every target is done",
+    ),
+    (
+        "jsonl",
+        "A JSON lines file per target, streamed as messages arrive",
+    ),
+    (
+        "sqlite:<path>",
+        "Peers, messages and runs upserted into a SQLite database",
+    ),
+    ("parquet:<path>", "A Parquet file with a row per message"),
+];
+
+impl FromStr for SinkKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("sqlite", path)) if !path.is_empty() => Ok(SinkKind::Sqlite(PathBuf::from(path))),
+            Some(("parquet", path)) if !path.is_empty() => {
+                Ok(SinkKind::Parquet(PathBuf::from(path)))
+            }
+            _ => match s {
+                "json" => Ok(SinkKind::Json),
+                "jsonl" => Ok(SinkKind::JsonLines),
+                other => Err(format!(
+                    "unknown sink '{}', expected one of: {}",
+                    other,
+                    SINK_VALUES.map(|(value, _)| value).join(", ")
+                )),
+            },
+        }
+    }
+}
+
+/// Parses `--sink`, listing `SINK_VALUES` as its possible values since the path taking ones can't be matched literally.
+#[derive(Debug, Clone, Copy)]
+pub struct SinkKindParser;
+
+impl TypedValueParser for SinkKindParser {
+    type Value = SinkKind;
+
+    fn parse_ref(
+        &self,
+        cmd: &Command,
+        _arg: Option<&Arg>,
+        value: &OsStr,
+    ) -> std::result::Result<Self::Value, ClapError> {
+        let value = value
+            .to_str()
+            .ok_or_else(|| ClapError::new(ErrorKind::InvalidUtf8).with_cmd(cmd))?;
+
+        value
+            .parse::<SinkKind>()
+            .map_err(|e| ClapError::raw(ErrorKind::InvalidValue, format!("{}\n", e)).with_cmd(cmd))
+    }
+
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = PossibleValue> + '_>> {
+        Some(Box::new(
+            SINK_VALUES
+                .iter()
+                .map(|(value, help)| PossibleValue::new(*value).help(*help)),
+        ))
+    }
+}
+
+impl ValueParserFactory for SinkKind {
+    type Parser = SinkKindParser;
+
+    fn value_parser() -> Self::Parser {
+        SinkKindParser
+    }
+}
+
+/// Run wide side of a sink, opened once and handing out an output to every target.
+#[derive(Debug, Clone)]
+pub enum RunSink {
+    Json,
+    JsonLines(DumpMetadata),
+    Sqlite(SqliteStore),
+    Parquet(ParquetStore),
+}
+
+impl RunSink {
+    pub async fn open(kind: &SinkKind, metadata: &DumpMetadata) -> Result<Self> {
+        let platform = metadata.platform.as_str();
+
+        Ok(match kind {
+            SinkKind::Json => RunSink::Json,
+            SinkKind::JsonLines => RunSink::JsonLines(metadata.clone()),
+            SinkKind::Sqlite(path) => {
+                RunSink::Sqlite(SqliteStore::open(path.clone(), platform).await?)
+            }
+            SinkKind::Parquet(path) => {
+                RunSink::Parquet(ParquetStore::create(path.clone(), platform).await?)
+            }
+        })
+    }
+
+    /// Output for a single target, `output_name` is the file stem used by file based sinks.
+    pub fn peer_output(&self, output_name: &str) -> PeerOutput {
+        match self {
+            RunSink::Json => PeerOutput::Json(vec![]),
+            RunSink::JsonLines(metadata) => PeerOutput::JsonLines(
+                PathBuf::from(format!("{}.jsonl", output_name)),
+                metadata.clone(),
+            ),
+            RunSink::Sqlite(store) => PeerOutput::Sqlite(store.clone()),
+            RunSink::Parquet(store) => PeerOutput::Parquet(store.clone()),
+        }
+    }
+
+    pub async fn finish(&self) -> Result<()> {
+        match self {
+            RunSink::Sqlite(store) => store.finish_run().await?,
+            RunSink::Parquet(store) => store.close().await?,
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// Opens every peer of a JSON lines dump, identifying the peer the following messages belong to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JsonLinesHeader {
+    pub peer: ResolvedPeer,
+}
+
+/// Any line of a JSON lines dump, for reading them back.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum JsonLinesRecord {
+    Metadata(DumpMetadata),
+    Header(JsonLinesHeader),
+    Message(PeerMessage),
+}
+
+#[derive(Debug)]
+pub enum PeerOutput {
+    Json(Vec<PeerMessage>),
+    JsonLines(PathBuf, DumpMetadata),
+    Sqlite(SqliteStore),
+    Parquet(ParquetStore),
+    /// A sync store, message lines appended after whatever it already holds. It opens with the same
+    /// records as a JSON lines dump, written by the run that created it
+    Store(PathBuf, DumpMetadata),
+}
+
+/// Receives the pages of a single target as they are fetched, checkpointing each once the output has it.
+#[derive(Debug)]
+pub struct PeerSink {
+    pub peer: ResolvedPeer,
+    checkpoint: CheckpointStore,
+    output: PeerOutput,
+    fetched: usize,
+    cursor: Option<String>,
+    media: Option<MediaStore>,
+    /// Whether pages are checkpointed, only runs that can be resumed pay for it
+    checkpointed: bool,
+}
+
+impl PeerSink {
+    pub fn new(peer: ResolvedPeer, checkpoint: CheckpointStore, output: PeerOutput) -> Self {
+        Self {
+            peer,
+            checkpoint,
+            output,
+            fetched: 0usize,
+            cursor: None,
+            media: None,
+            checkpointed: false,
+        }
+    }
+
+    /// Attachments of the target's messages are downloaded into `media` as they are fetched.
+    pub fn with_media(mut self, media: Option<MediaStore>) -> Self {
+        self.media = media;
+        self
+    }
+
+    /// Where attachments go when the run downloads media.
+    pub fn media(&self) -> Option<&MediaStore> {
+        self.media.as_ref()
+    }
+
+    /// Number of messages collected so far, including those restored from a checkpoint.
+    pub fn fetched(&self) -> usize {
+        self.fetched
+    }
+
+    /// Id of the last message collected, where paging continues from.
+    pub fn cursor(&self) -> Option<&String> {
+        self.cursor.as_ref()
+    }
+
+    /// Paging starts after `cursor` when there is no checkpoint to resume, sync seeds it from an earlier dump.
+    pub fn with_seed(mut self, cursor: Option<String>) -> Self {
+        self.cursor = cursor;
+        self
+    }
+
+    /// Prepares the output, picking up from the checkpoint when resuming one.
+    /// Pages are only checkpointed when `resume` is set.
+    pub async fn begin(&mut self, resume: bool) -> Result<()> {
+        self.checkpointed = resume;
+
+        let mut checkpoint = match resume {
+            true => self.checkpoint.load().await?,
+            false => None,
+        };
+
+        // An output that went missing since can't be appended to, the target starts over instead
+        if let Some(Checkpoint {
+            output: Some(ref output),
+            ..
+        }) = checkpoint
+            && !try_exists(output).await?
+        {
+            checkpoint = None;
+        }
+
+        match checkpoint {
+            Some(checkpoint) => self.restore(checkpoint).await,
+            None if resume => {
+                self.checkpoint.reset().await?;
+                self.start().await
+            }
+            None => self.start().await,
+        }
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        if let PeerOutput::Store(ref path, _) = self.output
+            && try_exists(path).await?
+            && metadata(path).await?.len() > 0u64
+        {
+            return Ok(());
+        }
+
+        match self.output {
+            PeerOutput::JsonLines(ref path, ref metadata)
+            | PeerOutput::Store(ref path, ref metadata) => {
+                let header = JsonLinesHeader {
+                    peer: self.peer.clone(),
+                };
+                write(
+                    path,
+                    format!("{}\n{}\n", to_string(metadata)?, to_string(&header)?),
+                )
+                .await?;
+            }
+            PeerOutput::Sqlite(ref store) => store.upsert_peer(&self.peer).await?,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    async fn restore(&mut self, checkpoint: Checkpoint) -> Result<()> {
+        match self.output {
+            PeerOutput::JsonLines(ref mut path, _) | PeerOutput::Store(ref mut path, _) => {
+                if let Some(ref output) = checkpoint.output {
+                    *path = output.clone();
+                }
+                if let Some(len) = checkpoint.output_len {
+                    truncate_output(path, len).await?;
+                }
+            }
+            PeerOutput::Sqlite(ref store) => store.upsert_peer(&self.peer).await?,
+            PeerOutput::Json(_) | PeerOutput::Parquet(_) => {
+                let restored = self.checkpoint.restore(&checkpoint).await?;
+                self.emit(restored).await?;
+            }
+        }
+
+        self.fetched = checkpoint.fetched;
+        self.cursor = Some(checkpoint.last_message_id);
+
+        Ok(())
+    }
+
+    /// File the output appends every page to, if it is written that way.
+    fn appended_path(&self) -> Option<&PathBuf> {
+        match self.output {
+            PeerOutput::JsonLines(ref path, _) | PeerOutput::Store(ref path, _) => Some(path),
+            _ => None,
+        }
+    }
+
+    pub async fn write_page(&mut self, page: Vec<PeerMessage>) -> Result<()> {
+        let Some(last) = page.last().cloned() else {
+            return Ok(());
+        };
+
+        self.fetched += page.len();
+        self.cursor = Some(last.id.clone());
+
+        if !self.checkpointed {
+            return self.emit(page).await;
+        }
+
+        // Outputs only written once the target is done journal the page, so a resumed run can rebuild them
+        if matches!(self.output, PeerOutput::Json(_) | PeerOutput::Parquet(_)) {
+            self.checkpoint.journal(&page).await?;
+        }
+
+        self.emit(page).await?;
+
+        let output = match self.appended_path() {
+            Some(path) => Some((path.clone(), metadata(path).await?.len())),
+            None => None,
+        };
+
+        self.checkpoint.record(&last, self.fetched, output).await
+    }
+
+    async fn emit(&mut self, page: Vec<PeerMessage>) -> Result<()> {
+        if page.is_empty() {
+            return Ok(());
+        }
+
+        match self.output {
+            PeerOutput::Json(ref mut messages) => messages.extend(page),
+            PeerOutput::JsonLines(ref path, _) | PeerOutput::Store(ref path, _) => {
+                append_json_lines(path, &page).await?
+            }
+            PeerOutput::Sqlite(ref store) => store.upsert_messages(&self.peer, page).await?,
+            PeerOutput::Parquet(ref store) => store.write(&self.peer, page).await?,
+        }
+
+        Ok(())
+    }
+
+    /// Hands back the collected peer when the output is a JSON document, for the caller to write.
+    pub fn finish(&mut self) -> Option<DumpedPeer> {
+        match self.output {
+            PeerOutput::Json(ref mut messages) => {
+                Some(DumpedPeer::new(self.peer.clone(), std::mem::take(messages)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Drops the checkpoint once the output is safely written.
+    pub async fn clear(&self) -> Result<()> {
+        if !self.checkpointed {
+            return Ok(());
+        }
+
+        self.checkpoint.clear().await
+    }
+}
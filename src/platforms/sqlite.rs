@@ -0,0 +1,259 @@
+use std::{
+    env,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use chrono::Utc;
+use color_eyre::eyre::{Result, eyre};
+use rusqlite::{Connection, params};
+use serde::Serialize;
+use tokio::task::spawn_blocking;
+
+use crate::platforms::structs::{PeerMessage, ResolvedPeer};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS runs (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        platform TEXT NOT NULL,
+        arguments TEXT NOT NULL,
+        started_at TEXT NOT NULL,
+        finished_at TEXT
+    );
+
+    CREATE TABLE IF NOT EXISTS peers (
+        platform TEXT NOT NULL,
+        peer_id TEXT NOT NULL,
+        peer_username TEXT NOT NULL,
+        peer_name TEXT,
+        parent_id TEXT,
+        parent_name TEXT,
+        thread_channel_id TEXT,
+        thread_starter_message_id TEXT,
+        last_run_id INTEGER NOT NULL REFERENCES runs (id),
+        PRIMARY KEY (platform, peer_id)
+    );
+
+    CREATE TABLE IF NOT EXISTS messages (
+        platform TEXT NOT NULL,
+        peer_id TEXT NOT NULL,
+        id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        message TEXT NOT NULL,
+        date TEXT NOT NULL,
+        reply_to TEXT,
+        edited_at TEXT,
+        forwarded_from TEXT,
+        attachments TEXT NOT NULL DEFAULT '[]',
+        reactions TEXT NOT NULL DEFAULT '[]',
+        extra TEXT NOT NULL DEFAULT '{}',
+        run_id INTEGER NOT NULL REFERENCES runs (id),
+        PRIMARY KEY (platform, peer_id, id),
+        FOREIGN KEY (platform, peer_id) REFERENCES peers (platform, peer_id)
+    );
+
+    CREATE INDEX IF NOT EXISTS messages_date ON messages (platform, peer_id, date);
+";
+
+/// Columns added after the first schema, appended to databases created before them.
+const PEER_DETAIL_COLUMNS: [(&str, &str); 5] = [
+    ("peer_name", "TEXT"),
+    ("parent_id", "TEXT"),
+    ("parent_name", "TEXT"),
+    ("thread_channel_id", "TEXT"),
+    ("thread_starter_message_id", "TEXT"),
+];
+
+const MESSAGE_DETAIL_COLUMNS: [(&str, &str); 6] = [
+    ("reply_to", "TEXT"),
+    ("edited_at", "TEXT"),
+    ("forwarded_from", "TEXT"),
+    ("attachments", "TEXT NOT NULL DEFAULT '[]'"),
+    ("reactions", "TEXT NOT NULL DEFAULT '[]'"),
+    ("extra", "TEXT NOT NULL DEFAULT '{}'"),
+];
+
+fn add_missing_columns(
+    connection: &Connection,
+    table: &str,
+    columns: &[(&str, &str)],
+) -> rusqlite::Result<()> {
+    let existing = connection
+        .prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+
+    for (column, definition) in columns.iter() {
+        if !existing.iter().any(|name| name == column) {
+            connection.execute_batch(&format!(
+                "ALTER TABLE {} ADD COLUMN {} {}",
+                table, column, definition
+            ))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn to_json<T: Serialize>(value: &T) -> rusqlite::Result<String> {
+    serde_json::to_string(value).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+}
+
+/// SQLite database shared by every target of a run. Each run gets a row in `runs`,
+/// messages are keyed by (platform, peer_id, id) so dumping the same window twice upserts.
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    connection: Arc<Mutex<Connection>>,
+    platform: String,
+    run_id: i64,
+}
+
+impl SqliteStore {
+    pub async fn open(path: PathBuf, platform: &str) -> Result<Self> {
+        let platform = platform.to_string();
+        let arguments = env::args().collect::<Vec<String>>().join(" ");
+
+        let (connection, run_id) = {
+            let platform = platform.clone();
+            spawn_blocking(move || -> Result<(Connection, i64)> {
+                let connection = Connection::open(path)?;
+                connection.pragma_update(None, "journal_mode", "WAL")?;
+                connection.pragma_update(None, "foreign_keys", "ON")?;
+                connection.execute_batch(SCHEMA)?;
+                add_missing_columns(&connection, "peers", &PEER_DETAIL_COLUMNS)?;
+                add_missing_columns(&connection, "messages", &MESSAGE_DETAIL_COLUMNS)?;
+
+                connection.execute(
+                    "INSERT INTO runs (platform, arguments, started_at) VALUES (?1, ?2, ?3)",
+                    params![platform, arguments, Utc::now().to_rfc3339()],
+                )?;
+                let run_id = connection.last_insert_rowid();
+
+                Ok((connection, run_id))
+            })
+            .await??
+        };
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+            platform,
+            run_id,
+        })
+    }
+
+    async fn with_connection<T, F>(&self, operation: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection, &str, i64) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        let platform = self.platform.clone();
+        let run_id = self.run_id;
+
+        spawn_blocking(move || {
+            let mut connection = connection
+                .lock()
+                .map_err(|e| eyre!(format!("SQLite connection poisoned: {}", e)))?;
+            Ok(operation(&mut connection, &platform, run_id)?)
+        })
+        .await?
+    }
+
+    pub async fn upsert_peer(&self, peer: &ResolvedPeer) -> Result<()> {
+        let peer = peer.clone();
+
+        self.with_connection(move |connection, platform, run_id| {
+            connection.execute(
+                "INSERT INTO peers (platform, peer_id, peer_username, peer_name, parent_id, parent_name,
+                    thread_channel_id, thread_starter_message_id, last_run_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                 ON CONFLICT (platform, peer_id) DO UPDATE SET
+                    peer_username = excluded.peer_username,
+                    peer_name = excluded.peer_name,
+                    parent_id = excluded.parent_id,
+                    parent_name = excluded.parent_name,
+                    thread_channel_id = excluded.thread_channel_id,
+                    thread_starter_message_id = excluded.thread_starter_message_id,
+                    last_run_id = excluded.last_run_id",
+                params![
+                    platform,
+                    peer.peer_id,
+                    peer.peer_username,
+                    peer.peer_name,
+                    peer.parent.as_ref().map(|parent| parent.id.clone()),
+                    peer.parent.as_ref().map(|parent| parent.name.clone()),
+                    peer.thread.as_ref().map(|thread| thread.channel_id.clone()),
+                    peer.thread
+                        .as_ref()
+                        .and_then(|thread| thread.starter_message_id.clone()),
+                    run_id
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Nested message details are stored as JSON text, query them with SQLite's `json_each`.
+    pub async fn upsert_messages(
+        &self,
+        peer: &ResolvedPeer,
+        messages: Vec<PeerMessage>,
+    ) -> Result<()> {
+        let peer_id = peer.peer_id.clone();
+
+        self.with_connection(move |connection, platform, run_id| {
+            let transaction = connection.transaction()?;
+            {
+                let mut statement = transaction.prepare_cached(
+                    "INSERT INTO messages (platform, peer_id, id, user_id, message, date, reply_to,
+                        edited_at, forwarded_from, attachments, reactions, extra, run_id)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+                     ON CONFLICT (platform, peer_id, id) DO UPDATE SET
+                        user_id = excluded.user_id,
+                        message = excluded.message,
+                        date = excluded.date,
+                        reply_to = excluded.reply_to,
+                        edited_at = excluded.edited_at,
+                        forwarded_from = excluded.forwarded_from,
+                        attachments = excluded.attachments,
+                        reactions = excluded.reactions,
+                        extra = excluded.extra,
+                        run_id = excluded.run_id",
+                )?;
+
+                for message in messages.iter() {
+                    statement.execute(params![
+                        platform,
+                        peer_id,
+                        message.id,
+                        message.user_id,
+                        message.message,
+                        message.date.to_rfc3339(),
+                        message.reply_to,
+                        message.edited_at.map(|date| date.to_rfc3339()),
+                        message.forwarded_from,
+                        to_json(&message.attachments)?,
+                        to_json(&message.reactions)?,
+                        to_json(&message.extra)?,
+                        run_id
+                    ])?;
+                }
+            }
+            transaction.commit()
+        })
+        .await
+    }
+
+    /// Stamps the run as finished, runs without a finish time were interrupted.
+    pub async fn finish_run(&self) -> Result<()> {
+        self.with_connection(move |connection, _, run_id| {
+            connection.execute(
+                "UPDATE runs SET finished_at = ?1 WHERE id = ?2",
+                params![Utc::now().to_rfc3339(), run_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+}
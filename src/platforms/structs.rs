@@ -1,12 +1,149 @@
-use std::fmt::Display;
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+/// Bounds and direction of a dump, shared by every target of a single run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DumpOptions {
+    pub limit: Option<u32>,
+    pub date_point: DateTime<Utc>,
+    pub until: Option<DateTime<Utc>>,
+    pub reverse: bool,
+    pub resume: bool,
+}
+
+impl DumpOptions {
+    pub fn new(
+        limit: Option<u32>,
+        date_point: DateTime<Utc>,
+        until: Option<DateTime<Utc>>,
+        reverse: bool,
+        resume: bool,
+    ) -> Self {
+        Self {
+            limit,
+            date_point,
+            until,
+            reverse,
+            resume,
+        }
+    }
+}
+
+/// Server or community a peer lives in, e.g. the guild of a Discord channel.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PeerParent {
+    pub id: String,
+    pub name: String,
+}
+
+impl PeerParent {
+    pub fn new(id: String, name: String) -> Self {
+        Self { id, name }
+    }
+}
+
+/// Where a thread hangs off: the channel it was opened in and the message it was started from, if any.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ThreadOrigin {
+    pub channel_id: String,
+    pub channel_name: Option<String>,
+    pub starter_message_id: Option<String>,
+}
+
+impl ThreadOrigin {
+    pub fn new(
+        channel_id: String,
+        channel_name: Option<String>,
+        starter_message_id: Option<String>,
+    ) -> Self {
+        Self {
+            channel_id,
+            channel_name,
+            starter_message_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PackedPeerKind {
+    User,
+    Bot,
+    Chat,
+    Megagroup,
+    Broadcast,
+    Gigagroup,
+}
+
+/// Telegram's packed chat, enough to address a peer again without resolving its username.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PackedPeer {
+    pub id: i64,
+    pub access_hash: Option<i64>,
+    pub kind: PackedPeerKind,
+}
+
+/// A target of a batch that could not be resolved or dumped, recorded in the run's failure report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TargetFailure {
+    pub target: String,
+    pub reason: String,
+}
+
+impl TargetFailure {
+    pub fn new(target: String, reason: String) -> Self {
+        Self { target, reason }
+    }
+}
+
+/// How a target was written in the file it was resolved from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerInputForm {
+    /// `name` or `@name`
+    Username,
+    /// `t.me/name`
+    PublicLink,
+    /// `t.me/c/<id>/<message>`
+    PostLink,
+    /// `t.me/+hash` or `t.me/joinchat/hash`
+    InviteLink,
+    /// A numeric id found among the session's dialogs
+    PeerId,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PeerInput {
+    pub form: PeerInputForm,
+    pub value: String,
+}
+
+impl PeerInput {
+    pub fn new(form: PeerInputForm, value: String) -> Self {
+        Self { form, value }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ResolvedPeer {
     pub peer_id: String,
     pub peer_username: String,
+    /// Display name, for peers known by more than an id or username
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<PeerParent>,
+    /// Set when the peer is a thread
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread: Option<ThreadOrigin>,
+    /// Set for Telegram peers, a stale access hash falls back to resolving the username
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub packed: Option<PackedPeer>,
+    /// What the peer was resolved from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<PeerInput>,
 }
 
 impl ResolvedPeer {
@@ -14,8 +151,38 @@ impl ResolvedPeer {
         Self {
             peer_id: id,
             peer_username: username,
+            peer_name: None,
+            parent: None,
+            thread: None,
+            packed: None,
+            input: None,
         }
     }
+
+    pub fn with_name(mut self, name: Option<String>) -> Self {
+        self.peer_name = name;
+        self
+    }
+
+    pub fn with_parent(mut self, parent: Option<PeerParent>) -> Self {
+        self.parent = parent;
+        self
+    }
+
+    pub fn with_thread(mut self, thread: Option<ThreadOrigin>) -> Self {
+        self.thread = thread;
+        self
+    }
+
+    pub fn with_packed(mut self, packed: Option<PackedPeer>) -> Self {
+        self.packed = packed;
+        self
+    }
+
+    pub fn with_input(mut self, input: Option<PeerInput>) -> Self {
+        self.input = input;
+        self
+    }
 }
 
 impl Display for ResolvedPeer {
@@ -28,12 +195,99 @@ impl Display for ResolvedPeer {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttachmentKind {
+    Image,
+    Video,
+    Audio,
+    Voice,
+    Sticker,
+    Document,
+    Other,
+}
+
+impl AttachmentKind {
+    /// Best guess from a MIME type, for platforms that only report one.
+    pub fn from_mime_type(mime_type: &str) -> Self {
+        match mime_type.split('/').next().unwrap_or_default() {
+            "image" => AttachmentKind::Image,
+            "video" => AttachmentKind::Video,
+            "audio" => AttachmentKind::Audio,
+            "application" | "text" => AttachmentKind::Document,
+            _ => AttachmentKind::Other,
+        }
+    }
+}
+
+impl FromStr for AttachmentKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "image" => Ok(AttachmentKind::Image),
+            "video" => Ok(AttachmentKind::Video),
+            "audio" => Ok(AttachmentKind::Audio),
+            "voice" => Ok(AttachmentKind::Voice),
+            "sticker" => Ok(AttachmentKind::Sticker),
+            "document" => Ok(AttachmentKind::Document),
+            "other" => Ok(AttachmentKind::Other),
+            other => Err(format!(
+                "unknown media kind '{}', expected one of: image, video, audio, voice, sticker, document, other",
+                other
+            )),
+        }
+    }
+}
+
+/// File attached to a message. Discord hands out a CDN `url`, Telegram a `file_id` to download it through.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Attachment {
+    pub kind: AttachmentKind,
+    pub filename: Option<String>,
+    pub mime_type: Option<String>,
+    pub size: Option<u64>,
+    pub url: Option<String>,
+    pub file_id: Option<String>,
+    /// Content hash of the downloaded copy, set when the dump ran with media downloads
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Why downloading it failed, the dump goes on without the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Reaction {
+    pub emoji: String,
+    pub count: u32,
+}
+
+impl Reaction {
+    pub fn new(emoji: String, count: u32) -> Self {
+        Self { emoji, count }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct PeerMessage {
     pub id: String,
     pub user_id: String,
     pub message: String,
     pub date: DateTime<Utc>,
+    #[serde(default)]
+    pub reply_to: Option<String>,
+    #[serde(default)]
+    pub edited_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub forwarded_from: Option<String>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+    /// Platform specific details that have no shared field, e.g. Telegram view counts or Discord message types.
+    #[serde(default)]
+    pub extra: BTreeMap<String, String>,
 }
 
 impl PeerMessage {
@@ -43,6 +297,12 @@ impl PeerMessage {
             user_id,
             message,
             date,
+            reply_to: None,
+            edited_at: None,
+            forwarded_from: None,
+            attachments: vec![],
+            reactions: vec![],
+            extra: BTreeMap::new(),
         }
     }
 }
@@ -65,10 +325,7 @@ pub struct DumpedPeer {
 
 impl DumpedPeer {
     pub fn new(peer: ResolvedPeer, chunks: Vec<PeerMessage>) -> Self {
-        Self {
-            peer: peer,
-            chunks: chunks,
-        }
+        Self { peer, chunks }
     }
 }
 
@@ -76,7 +333,7 @@ impl Display for DumpedPeer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "[DUMPED_PEER]\n{}\n\n[", self.peer)?;
         for item in self.chunks.iter() {
-            write!(f, "   {},\n", item)?;
+            writeln!(f, "   {},", item)?;
         }
         write!(f, "]")
     }
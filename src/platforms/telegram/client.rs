@@ -1,12 +1,14 @@
-use crate::platforms::telegram::structs::TelegramClient;
+use crate::{arg::const_get_application_arguments, platforms::telegram::structs::TelegramClient};
 use color_eyre::eyre::Result;
 use std::sync::Arc;
 use tokio::sync::OnceCell;
 
-pub const TELEGRAM_CLIENT: OnceCell<Arc<TelegramClient>> = OnceCell::const_new();
+pub static TELEGRAM_CLIENT: OnceCell<Arc<TelegramClient>> = OnceCell::const_new();
 
 async fn init_telegram_client() -> Result<Arc<TelegramClient>> {
-    Ok(Arc::new(TelegramClient::init().await?))
+    let args = const_get_application_arguments().await?;
+
+    Ok(Arc::new(TelegramClient::init(!args.no_prompt).await?))
 }
 
 pub async fn const_get_telegram_client() -> Result<Arc<TelegramClient>> {
@@ -1,105 +1,48 @@
 use chrono::{DateTime, Utc};
 use encoding_rs::UTF_8;
-use futures::future::try_join_all;
+use futures::future::join_all;
+use grammers_client::types::Chat;
 use indicatif::ProgressBar;
 use serde_json::{from_slice, to_string};
-use std::{path::PathBuf, sync::Arc};
-use tokio::{
-    fs::{read, write},
-    sync::Semaphore,
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
 };
+use tokio::fs::{read, write};
 
-use color_eyre::eyre::{Result, eyre};
+use color_eyre::eyre::{Report, Result, eyre};
 
 use crate::{
-    error::{DecodingError, DeserializationError},
-    platforms::structs::{DumpedPeer, ResolvedPeer},
+    error::{DecodingError, DeserializationError, UsernameResolveError},
+    platforms::platform::Platform,
+    platforms::runner::report_failures,
+    platforms::sink::PeerSink,
+    platforms::structs::{DumpOptions, ResolvedPeer, TargetFailure},
     platforms::telegram::client::const_get_telegram_client,
-    platforms::telegram::structs::TelegramClient,
-    visual::new_multi_progress,
+    platforms::telegram::structs::{
+        DialogFilter, DialogSummary, TelegramClient, TelegramTarget, chat_peer, peer_failure,
+    },
+    utils::{error_reason, file_timestamp},
+    visual::{new_multi_progress, print_table},
 };
 
-async fn resolve_single_username(
+async fn resolve_single_target(
     client: Arc<TelegramClient>,
-    username: String,
+    input: String,
+    target: TelegramTarget,
+    dialogs: Arc<Vec<Chat>>,
     progress: Option<ProgressBar>,
 ) -> Result<ResolvedPeer> {
-    let user = client.resolve_username(username, progress).await?;
-
-    Ok(ResolvedPeer::new(
-        user.id().to_string(),
-        user.username().unwrap_or("PRIVATE_USERNAME").to_string(),
-    ))
-}
-
-async fn dump_for_single_username(
-    semaphore: Arc<Semaphore>,
-    client: Arc<TelegramClient>,
-    peer: ResolvedPeer,
-    date_point: DateTime<Utc>,
-    limit: Option<u32>,
-    reverse: bool,
-    execution_time: DateTime<Utc>,
-    progress: Option<ProgressBar>,
-) -> Result<()> {
-    if let Some(ref prog) = progress {
-        prog.set_message(peer.peer_username.clone());
-
-        if semaphore.available_permits() == 0usize {
-            prog.set_message(format!(
-                "{} - Awaiting permit...",
-                peer.peer_username.clone()
-            ));
-        }
-    }
-
-    let permit = semaphore.acquire().await?;
-
-    if let Some(ref prog) = progress {
-        prog.set_message(peer.peer_username.clone());
-    }
-
-    let messages = client
-        .dump_username(
-            peer.peer_username.clone(),
-            limit,
-            date_point,
-            reverse,
-            progress,
-        )
-        .await?;
-
-    drop(permit);
-
-    let dumped_peer = DumpedPeer::new(peer.clone(), messages);
-
-    let actual_limit = if let Some(lim) = limit {
-        format!("_{}", lim.to_string())
-    } else {
-        "".to_string()
-    };
-
-    write(
-        format!(
-            "telegram_{}{}_{}.json",
-            peer.peer_username.clone(),
-            actual_limit,
-            &&execution_time
-                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
-                .replace("+", "_")
-                .replace(":", "_")
-        ),
-        to_string(&dumped_peer)?,
-    )
-    .await?;
-
-    Ok(())
+    client
+        .resolve_target(&input, &target, &dialogs, progress)
+        .await
 }
 
-pub async fn resolve_usernames(filepath: PathBuf) -> Result<()> {
+pub async fn resolve_usernames(filepath: PathBuf, fail_on_error: bool) -> Result<()> {
     let execution_time = Utc::now();
 
-    let usernames = {
+    let inputs = {
         let buf = read(&filepath).await?;
         let (string, _, error) = UTF_8.decode(&buf);
 
@@ -111,95 +54,277 @@ pub async fn resolve_usernames(filepath: PathBuf) -> Result<()> {
 
         string
             .lines()
-            .map(|s| s.to_string())
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
             .collect::<Vec<String>>()
     };
 
+    let (parsed, mut failures) = parse_targets(&inputs);
+
     let client = const_get_telegram_client().await?;
 
+    let dialogs = if parsed.iter().any(|(_, t)| t.needs_dialogs()) {
+        Arc::new(
+            client
+                .load_dialogs()
+                .await?
+                .iter()
+                .map(|dialog| dialog.chat().clone())
+                .collect(),
+        )
+    } else {
+        Arc::new(vec![])
+    };
+
     let (multiprog, style) = new_multi_progress()?;
 
-    let futures = usernames
+    let futures = parsed
         .iter()
-        .map(|username| {
+        .map(|(input, target)| {
             let progress = multiprog.add(ProgressBar::new(1));
             progress.set_style(style.clone());
-            progress.set_message(username.clone());
-            resolve_single_username(client.clone(), username.clone(), Some(progress))
+            progress.set_message(input.clone());
+            resolve_single_target(
+                client.clone(),
+                input.clone(),
+                target.clone(),
+                dialogs.clone(),
+                Some(progress),
+            )
         })
         .collect::<Vec<_>>();
 
-    let resolved = try_join_all(futures).await?;
+    let mut resolved: Vec<ResolvedPeer> = vec![];
+    // Only inputs that failed to resolve are reported, anything else aborts before the output is written
+    for ((input, _), result) in parsed.iter().zip(join_all(futures).await) {
+        match result {
+            Ok(peer) => resolved.push(peer),
+            Err(e) if e.downcast_ref::<UsernameResolveError>().is_some() => {
+                failures.push(TargetFailure::new(input.clone(), error_reason(&e)))
+            }
+            Err(e) => return Err(e),
+        }
+    }
 
+    write_resolution(
+        Path::new(""),
+        &resolved,
+        failures,
+        inputs.len(),
+        &execution_time,
+        fail_on_error,
+    )
+    .await
+}
+
+/// Parses every input line, the ones that aren't a target are reported as failed rather than
+/// stopping the others.
+fn parse_targets(inputs: &[String]) -> (Vec<(String, TelegramTarget)>, Vec<TargetFailure>) {
+    let mut parsed: Vec<(String, TelegramTarget)> = vec![];
+    let mut failures: Vec<TargetFailure> = vec![];
+
+    for input in inputs.iter() {
+        match TelegramTarget::from_str(input) {
+            Ok(target) => parsed.push((input.clone(), target)),
+            Err(reason) => failures.push(TargetFailure::new(input.clone(), reason)),
+        }
+    }
+
+    (parsed, failures)
+}
+
+/// Writes the resolved peers into `directory`, then the report of the inputs that failed next to them.
+async fn write_resolution(
+    directory: &Path,
+    resolved: &[ResolvedPeer],
+    failures: Vec<TargetFailure>,
+    total: usize,
+    execution_time: &DateTime<Utc>,
+    fail_on_error: bool,
+) -> Result<()> {
     write(
-        format!(
+        directory.join(format!(
             "telegram_resolved_peers_{}_{}.json",
             resolved.len(),
-            &&execution_time
-                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
-                .replace("+", "_")
-                .replace(":", "_")
-        ),
-        to_string(&resolved)?,
+            file_timestamp(execution_time)
+        )),
+        to_string(resolved)?,
     )
     .await?;
 
+    report_failures(
+        &directory.join("telegram_resolve").to_string_lossy(),
+        failures,
+        total,
+        execution_time,
+        fail_on_error,
+    )
+    .await
+}
+
+/// Prints the session's dialogs that pass `filter`. With `output`, they are written there as resolved
+/// peers instead, ready for dump-messages.
+pub async fn list_dialogs(filter: DialogFilter, output: Option<PathBuf>) -> Result<()> {
+    let dialogs = const_get_telegram_client()
+        .await?
+        .load_dialogs()
+        .await?
+        .iter()
+        .map(DialogSummary::from)
+        .filter(|dialog| filter.matches(dialog))
+        .collect::<Vec<DialogSummary>>();
+
+    if let Some(output) = output {
+        let peers = dialogs
+            .iter()
+            .map(|dialog| chat_peer(&dialog.chat))
+            .collect::<Vec<ResolvedPeer>>();
+
+        write(output, to_string(&peers)?).await?;
+        return Ok(());
+    }
+
+    let rows = dialogs
+        .iter()
+        .map(|dialog| {
+            vec![
+                dialog.chat.id().to_string(),
+                dialog.kind.name().to_string(),
+                dialog.chat.name().to_string(),
+                dialog.chat.username().unwrap_or_default().to_string(),
+                dialog.unread_count.to_string(),
+                if dialog.archived { "yes" } else { "" }.to_string(),
+            ]
+        })
+        .collect::<Vec<Vec<String>>>();
+
+    print_table(
+        &["ID", "KIND", "NAME", "USERNAME", "UNREAD", "ARCHIVED"],
+        &rows,
+    );
+
     Ok(())
 }
 
-pub async fn dump_messages(
-    filepath: PathBuf,
-    date_point: DateTime<Utc>,
-    limit: Option<u32>,
-    reverse: bool,
-) -> Result<()> {
-    let execution_time = Utc::now();
+async fn read_resolved_peers(filepath: &Path) -> Result<Vec<ResolvedPeer>> {
+    let buf = read(filepath).await?;
+    match from_slice::<Vec<ResolvedPeer>>(&buf) {
+        Ok(data) => Ok(data),
+        Err(e) => Err(eyre!(DeserializationError::new(
+            filepath.to_string_lossy().to_string(),
+            e.to_string()
+        ))),
+    }
+}
 
-    let usernames = {
-        let buf = read(&filepath).await?;
-        match from_slice::<Vec<ResolvedPeer>>(&buf) {
-            Ok(data) => data,
-            Err(e) => {
-                return Err(eyre!(DeserializationError::new(
-                    filepath.to_string_lossy().to_string(),
-                    e.to_string()
-                )));
-            }
-        }
-    };
+impl Platform for TelegramClient {
+    type Target = ResolvedPeer;
 
-    let client = const_get_telegram_client().await?;
+    const NAME: &'static str = "telegram";
 
-    let (multiprog, style) = new_multi_progress()?;
+    const CONCURRENCY: usize = 3;
 
-    let implied_limit = match limit {
-        Some(lim) => lim as u64,
-        None => 1e+8 as u64,
-    };
+    const SINGLE_JSON_DOCUMENT: bool = false;
 
-    let semaphore = Arc::new(Semaphore::new(3));
+    async fn client() -> Result<Arc<Self>> {
+        const_get_telegram_client().await
+    }
 
-    let futures = usernames
-        .iter()
-        .map(|p| {
-            let sclone = semaphore.clone();
-            let progress = multiprog.add(ProgressBar::new(implied_limit));
-            progress.set_style(style.clone());
-            progress.set_message(format!("{} - Awaiting to start", p.peer_username.clone()));
-            dump_for_single_username(
-                sclone,
-                client.clone(),
-                p.clone(),
-                date_point.clone(),
-                limit.clone(),
-                reverse,
-                execution_time.clone(),
-                Some(progress),
-            )
-        })
-        .collect::<Vec<_>>();
+    async fn list(targets_file: &Path) -> Result<Vec<ResolvedPeer>> {
+        read_resolved_peers(targets_file).await
+    }
 
-    try_join_all(futures).await?;
+    fn label(target: &ResolvedPeer) -> String {
+        target.peer_username.clone()
+    }
 
-    Ok(())
+    async fn resolve(&self, target: &ResolvedPeer) -> Result<ResolvedPeer> {
+        Ok(target.clone())
+    }
+
+    fn target_failure(error: &Report) -> Option<String> {
+        peer_failure(error)
+    }
+
+    async fn dump(
+        &self,
+        target: &ResolvedPeer,
+        options: &DumpOptions,
+        sink: &mut PeerSink,
+        progress: Option<ProgressBar>,
+    ) -> Result<()> {
+        self.dump_peer(target, *options, sink, progress).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::{create_dir_all, read_dir, remove_dir_all};
+
+    fn written_files(directory: &Path) -> Vec<String> {
+        let mut names = read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn malformed_lines_fail_alone() {
+        let inputs = ["@durov", "t.me/c/channel/15", "https://t.me/", "t.me/+AbC"]
+            .map(str::to_string)
+            .to_vec();
+
+        let (parsed, failures) = parse_targets(&inputs);
+
+        assert_eq!(
+            parsed
+                .iter()
+                .map(|(input, _)| input.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["@durov", "t.me/+AbC"]
+        );
+        assert_eq!(
+            failures
+                .iter()
+                .map(|f| f.target.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["t.me/c/channel/15", "https://t.me/"]
+        );
+    }
+
+    #[tokio::test]
+    async fn batch_with_a_bad_line_writes_peers_and_report() {
+        let directory = std::env::temp_dir().join(format!("resolve_batch_{}", std::process::id()));
+        create_dir_all(&directory).unwrap();
+
+        let inputs = ["t.me/c/channel/15", "@durov"].map(str::to_string).to_vec();
+        let (_, failures) = parse_targets(&inputs);
+        let resolved = vec![ResolvedPeer::new("1".to_string(), "durov".to_string())];
+        let execution_time = DateTime::from_timestamp_secs(1727740800).unwrap();
+
+        let written = write_resolution(
+            &directory,
+            &resolved,
+            failures,
+            inputs.len(),
+            &execution_time,
+            true,
+        )
+        .await;
+
+        assert!(written.is_err());
+        assert_eq!(
+            written_files(&directory),
+            vec![
+                "telegram_resolve_failures_1_2024-10-01T00_00_00Z.json",
+                "telegram_resolved_peers_1_2024-10-01T00_00_00Z.json",
+            ]
+        );
+
+        remove_dir_all(&directory).unwrap();
+    }
 }
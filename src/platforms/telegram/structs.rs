@@ -1,54 +1,591 @@
-use std::time::Duration;
+use std::{collections::BTreeMap, str::FromStr, time::Duration};
 
-use chrono::{DateTime, Utc};
-use color_eyre::eyre::{Result, eyre};
+use color_eyre::eyre::{Report, Result, eyre};
 use grammers_client::{
-    Client, Config, InitParams,
+    Client, Config, InitParams, InvocationError, SignInError, grammers_tl_types as tl,
     session::Session,
-    types::{Chat, Message},
+    types::{Chat, ChatMap, Dialog, Media, Message, PackedChat, PackedType, User, media::Document},
 };
 use indicatif::ProgressBar;
-use inquire::Text;
+use inquire::{Password, PasswordDisplayMode, Text};
 use tokio::time::sleep;
 
-use crate::{env::Environment, error::UsernameResolveError, platforms::structs::PeerMessage};
+use crate::{
+    env::Environment,
+    error::{StalePeerError, TelegramAuthorizationError, UsernameResolveError},
+    platforms::{
+        sink::PeerSink,
+        structs::{
+            Attachment, AttachmentKind, DumpOptions, PackedPeer, PackedPeerKind, PeerInput,
+            PeerInputForm, PeerMessage, Reaction, ResolvedPeer,
+        },
+    },
+    utils::error_reason,
+};
+
+/// Largest page `messages.getHistory` will hand back in one request.
+const HISTORY_PAGE_SIZE: usize = 100;
+
+fn peer_label(peer: &tl::enums::Peer) -> String {
+    match peer {
+        tl::enums::Peer::User(user) => format!("user{}", user.user_id),
+        tl::enums::Peer::Chat(chat) => format!("chat{}", chat.chat_id),
+        tl::enums::Peer::Channel(channel) => format!("channel{}", channel.channel_id),
+    }
+}
+
+fn media_attachment(media: Media) -> Option<Attachment> {
+    let document_attachment = |kind: Option<AttachmentKind>, document: &Document| {
+        let mime_type = document.mime_type().map(|mime| mime.to_string());
+        let kind = kind.unwrap_or(match mime_type.as_deref() {
+            Some("audio/ogg") => AttachmentKind::Voice,
+            Some(mime) => AttachmentKind::from_mime_type(mime),
+            None => AttachmentKind::Document,
+        });
+
+        Attachment {
+            kind,
+            filename: Some(document.name().to_string()).filter(|name| !name.is_empty()),
+            mime_type,
+            size: u64::try_from(document.size()).ok(),
+            url: None,
+            file_id: Some(document.id().to_string()),
+            sha256: None,
+            download_error: None,
+        }
+    };
+
+    match media {
+        Media::Photo(photo) => Some(Attachment {
+            kind: AttachmentKind::Image,
+            filename: None,
+            mime_type: Some("image/jpeg".to_string()),
+            size: None,
+            url: None,
+            file_id: Some(photo.id().to_string()),
+            sha256: None,
+            download_error: None,
+        }),
+        Media::Document(document) => Some(document_attachment(None, &document)),
+        Media::Sticker(sticker) => Some(document_attachment(
+            Some(AttachmentKind::Sticker),
+            &sticker.document,
+        )),
+        _ => None,
+    }
+}
+
+fn message_reactions(reactions: &Option<tl::enums::MessageReactions>) -> Vec<Reaction> {
+    let Some(tl::enums::MessageReactions::Reactions(reactions)) = reactions else {
+        return vec![];
+    };
+
+    reactions
+        .results
+        .iter()
+        .filter_map(|tl::enums::ReactionCount::Count(result)| {
+            let emoji = match &result.reaction {
+                tl::enums::Reaction::Emoji(emoji) => emoji.emoticon.clone(),
+                tl::enums::Reaction::CustomEmoji(custom) => format!("custom{}", custom.document_id),
+                _ => return None,
+            };
+
+            Some(Reaction::new(emoji, result.count.max(0) as u32))
+        })
+        .collect()
+}
+
+impl From<&Message> for PeerMessage {
+    fn from(message: &Message) -> Self {
+        let user_id = match message.sender() {
+            Some(sender) => format!("user{}", sender.id()).to_string(),
+            None => "userXXX".to_string(),
+        };
+
+        let forwarded_from = message.forward_header().and_then(|header| {
+            let tl::enums::MessageFwdHeader::Header(header) = header;
+            header
+                .from_id
+                .as_ref()
+                .map(peer_label)
+                .or(header.from_name.clone())
+        });
+
+        let raw = &message.raw;
+        let mut extra = BTreeMap::new();
+        if let Some(views) = raw.views {
+            extra.insert("views".to_string(), views.to_string());
+        }
+        if let Some(forwards) = raw.forwards {
+            extra.insert("forwards".to_string(), forwards.to_string());
+        }
+        if let Some(author) = &raw.post_author {
+            extra.insert("post_author".to_string(), author.clone());
+        }
+        if let Some(grouped_id) = raw.grouped_id {
+            extra.insert("grouped_id".to_string(), grouped_id.to_string());
+        }
+        if raw.pinned {
+            extra.insert("pinned".to_string(), true.to_string());
+        }
+
+        PeerMessage {
+            reply_to: message.reply_to_message_id().map(|id| id.to_string()),
+            edited_at: message.edit_date(),
+            forwarded_from,
+            attachments: message
+                .media()
+                .and_then(media_attachment)
+                .into_iter()
+                .collect(),
+            reactions: message_reactions(&raw.reactions),
+            extra,
+            ..PeerMessage::new(
+                message.id().to_string(),
+                user_id,
+                message.text().to_string(),
+                message.date(),
+            )
+        }
+    }
+}
+
+/// Converts `message`, downloading its media first when the run keeps attachments.
+async fn collect_message(message: &Message, sink: &PeerSink) -> PeerMessage {
+    let mut peer_message = PeerMessage::from(message);
+
+    let Some(media) = sink.media() else {
+        return peer_message;
+    };
+
+    // A Telegram message carries at most one media item
+    for (index, attachment) in peer_message.attachments.iter_mut().enumerate() {
+        if !media.wants(attachment) {
+            continue;
+        }
+
+        media
+            .download(
+                &sink.peer.peer_id,
+                &peer_message.id,
+                index,
+                attachment,
+                async |temporary| Ok(message.download_media(&temporary).await?),
+            )
+            .await;
+    }
+
+    peer_message
+}
+
+/// Errors Telegram answers with when a stored access hash no longer addresses the peer.
+const STALE_PEER_ERRORS: [&str; 4] = [
+    "CHANNEL_INVALID",
+    "CHAT_ID_INVALID",
+    "PEER_ID_INVALID",
+    "USER_ID_INVALID",
+];
+
+/// Errors Telegram answers with when the account may not read a peer, or it is gone.
+const PEER_REFUSED_ERRORS: [&str; 5] = [
+    "CHANNEL_PRIVATE",
+    "CHANNEL_PUBLIC_GROUP_NA",
+    "CHAT_FORBIDDEN",
+    "CHAT_RESTRICTED",
+    "USER_BANNED_IN_CHANNEL",
+];
+
+/// Reason a dump of a single peer failed on its own: it could not be resolved, its access hash went
+/// stale for good, or Telegram refuses the account access to it.
+pub fn peer_failure(error: &Report) -> Option<String> {
+    if error.downcast_ref::<UsernameResolveError>().is_some()
+        || error.downcast_ref::<StalePeerError>().is_some()
+    {
+        return Some(error_reason(error));
+    }
+
+    match error.downcast_ref::<InvocationError>() {
+        Some(InvocationError::Rpc(rpc_error))
+            if PEER_REFUSED_ERRORS.contains(&rpc_error.name.as_str())
+                || STALE_PEER_ERRORS.contains(&rpc_error.name.as_str()) =>
+        {
+            Some(format!("Telegram refused the peer ({})", rpc_error.name))
+        }
+        _ => None,
+    }
+}
+
+fn is_stale_peer(error: &Report) -> bool {
+    match error.downcast_ref::<InvocationError>() {
+        Some(InvocationError::Rpc(rpc_error)) => {
+            STALE_PEER_ERRORS.contains(&rpc_error.name.as_str())
+        }
+        _ => false,
+    }
+}
+
+impl From<PackedChat> for PackedPeer {
+    fn from(chat: PackedChat) -> Self {
+        Self {
+            id: chat.id,
+            access_hash: chat.access_hash,
+            kind: match chat.ty {
+                PackedType::User => PackedPeerKind::User,
+                PackedType::Bot => PackedPeerKind::Bot,
+                PackedType::Chat => PackedPeerKind::Chat,
+                PackedType::Megagroup => PackedPeerKind::Megagroup,
+                PackedType::Broadcast => PackedPeerKind::Broadcast,
+                PackedType::Gigagroup => PackedPeerKind::Gigagroup,
+            },
+        }
+    }
+}
+
+impl From<PackedPeer> for PackedChat {
+    fn from(peer: PackedPeer) -> Self {
+        Self {
+            ty: match peer.kind {
+                PackedPeerKind::User => PackedType::User,
+                PackedPeerKind::Bot => PackedType::Bot,
+                PackedPeerKind::Chat => PackedType::Chat,
+                PackedPeerKind::Megagroup => PackedType::Megagroup,
+                PackedPeerKind::Broadcast => PackedType::Broadcast,
+                PackedPeerKind::Gigagroup => PackedType::Gigagroup,
+            },
+            id: peer.id,
+            access_hash: peer.access_hash,
+        }
+    }
+}
+
+/// Offset between a channel's id and the `-100` prefixed form used by bots and most exports.
+const CHANNEL_ID_OFFSET: i64 = 1_000_000_000_000;
+
+/// A line of the targets file given to resolve-users.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TelegramTarget {
+    Username(String, PeerInputForm),
+    /// Private channel a `t.me/c/<id>/<message>` link points into
+    PostLink(i64),
+    Invite(String),
+    /// Bare id, as found in the session's dialogs
+    PeerId(i64),
+}
+
+impl TelegramTarget {
+    fn form(&self) -> PeerInputForm {
+        match self {
+            TelegramTarget::Username(_, form) => *form,
+            TelegramTarget::PostLink(_) => PeerInputForm::PostLink,
+            TelegramTarget::Invite(_) => PeerInputForm::InviteLink,
+            TelegramTarget::PeerId(_) => PeerInputForm::PeerId,
+        }
+    }
+
+    /// Post links and ids can only be looked up among the dialogs the session already has.
+    pub fn needs_dialogs(&self) -> bool {
+        matches!(
+            self,
+            TelegramTarget::PostLink(_) | TelegramTarget::PeerId(_)
+        )
+    }
+}
+
+impl FromStr for TelegramTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let input = s.trim();
+        let link = ["https://", "http://"]
+            .iter()
+            .find_map(|scheme| input.strip_prefix(scheme))
+            .unwrap_or(input);
+        let link = link.strip_prefix("www.").unwrap_or(link);
+
+        let path = ["t.me/", "telegram.me/", "telegram.dog/"]
+            .iter()
+            .find_map(|host| link.strip_prefix(host));
+
+        let Some(path) = path else {
+            if let Some(username) = input.strip_prefix('@') {
+                return Ok(TelegramTarget::Username(
+                    username.to_string(),
+                    PeerInputForm::Username,
+                ));
+            }
+
+            return match input.parse::<i64>() {
+                Ok(id) if id < -CHANNEL_ID_OFFSET => {
+                    Ok(TelegramTarget::PeerId(-id - CHANNEL_ID_OFFSET))
+                }
+                Ok(id) => Ok(TelegramTarget::PeerId(id.abs())),
+                Err(_) => Ok(TelegramTarget::Username(
+                    input.to_string(),
+                    PeerInputForm::Username,
+                )),
+            };
+        };
+
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+        let segments = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<&str>>();
+
+        match segments.as_slice() {
+            ["c", id, ..] => id
+                .parse::<i64>()
+                .map(TelegramTarget::PostLink)
+                .map_err(|_| format!("'{}' is not a valid post link", input)),
+            ["joinchat", hash, ..] => Ok(TelegramTarget::Invite(hash.to_string())),
+            [invite, ..] if invite.starts_with('+') => match &invite[1..] {
+                "" => Err(format!("'{}' does not point to a chat", input)),
+                // t.me/+<phone number> opens a chat with a user, it is not an invite
+                number if number.chars().all(|c| c.is_ascii_digit()) => Err(format!(
+                    "'{}' is a phone number link, give the user's username instead",
+                    input
+                )),
+                hash => Ok(TelegramTarget::Invite(hash.to_string())),
+            },
+            ["s", username, ..] => Ok(TelegramTarget::Username(
+                username.to_string(),
+                PeerInputForm::PublicLink,
+            )),
+            // Reserved paths that only point to a chat with an id, hash or name after them
+            ["c" | "joinchat" | "s"] | [] => Err(format!("'{}' does not point to a chat", input)),
+            [username, ..] => Ok(TelegramTarget::Username(
+                username.to_string(),
+                PeerInputForm::PublicLink,
+            )),
+        }
+    }
+}
+
+/// Kinds of chat a dialog can be, as told apart in the Telegram apps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DialogKind {
+    Channel,
+    Supergroup,
+    Group,
+    Private,
+}
+
+impl DialogKind {
+    fn of(chat: &Chat) -> Self {
+        match PackedPeer::from(chat.pack()).kind {
+            PackedPeerKind::User | PackedPeerKind::Bot => DialogKind::Private,
+            PackedPeerKind::Chat => DialogKind::Group,
+            PackedPeerKind::Megagroup | PackedPeerKind::Gigagroup => DialogKind::Supergroup,
+            PackedPeerKind::Broadcast => DialogKind::Channel,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DialogKind::Channel => "channel",
+            DialogKind::Supergroup => "supergroup",
+            DialogKind::Group => "group",
+            DialogKind::Private => "private",
+        }
+    }
+}
+
+impl FromStr for DialogKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "channel" => Ok(DialogKind::Channel),
+            "supergroup" => Ok(DialogKind::Supergroup),
+            "group" => Ok(DialogKind::Group),
+            "private" => Ok(DialogKind::Private),
+            other => Err(format!(
+                "unknown dialog kind '{}', expected one of: channel, supergroup, group, private",
+                other
+            )),
+        }
+    }
+}
+
+/// A dialog with the state list-dialogs filters and prints.
+#[derive(Debug, Clone)]
+pub struct DialogSummary {
+    pub chat: Chat,
+    pub kind: DialogKind,
+    pub unread_count: i32,
+    pub archived: bool,
+}
+
+impl From<&Dialog> for DialogSummary {
+    fn from(dialog: &Dialog) -> Self {
+        // Folder 1 is the archive
+        let (unread_count, archived) = match &dialog.raw {
+            tl::enums::Dialog::Dialog(raw) => (raw.unread_count, raw.folder_id == Some(1)),
+            tl::enums::Dialog::Folder(_) => (0, false),
+        };
+
+        Self {
+            chat: dialog.chat().clone(),
+            kind: DialogKind::of(dialog.chat()),
+            unread_count,
+            archived,
+        }
+    }
+}
+
+/// Which dialogs list-dialogs keeps, every filter left unset lets everything through.
+#[derive(Debug, Clone, Default)]
+pub struct DialogFilter {
+    pub kinds: Vec<DialogKind>,
+    /// Matched case insensitively against the title and username
+    pub name: Option<String>,
+    pub unread: Option<bool>,
+    pub archived: Option<bool>,
+}
+
+impl DialogFilter {
+    pub fn matches(&self, dialog: &DialogSummary) -> bool {
+        let kind = self.kinds.is_empty() || self.kinds.contains(&dialog.kind);
+        let name = self.name.as_ref().is_none_or(|name| {
+            let name = name.to_lowercase();
+            dialog.chat.name().to_lowercase().contains(&name)
+                || dialog
+                    .chat
+                    .username()
+                    .is_some_and(|username| username.to_lowercase().contains(&name))
+        });
+        let unread = self
+            .unread
+            .is_none_or(|unread| unread == (dialog.unread_count > 0));
+        let archived = self
+            .archived
+            .is_none_or(|archived| archived == dialog.archived);
+
+        kind && name && unread && archived
+    }
+}
+
+/// Username a peer can be resolved by again. Peers without one record their id in its place, or
+/// `PRIVATE_USERNAME` in targets files written by older versions.
+fn public_username(peer: &ResolvedPeer) -> Option<&str> {
+    Some(peer.peer_username.as_str())
+        .filter(|username| *username != peer.peer_id && *username != "PRIVATE_USERNAME")
+}
+
+/// Resolved peer recorded for a chat grammers already knows.
+pub fn chat_peer(chat: &Chat) -> ResolvedPeer {
+    ResolvedPeer::new(
+        chat.id().to_string(),
+        chat.username()
+            .map(|username| username.to_string())
+            .unwrap_or_else(|| chat.id().to_string()),
+    )
+    .with_name(Some(chat.name().to_string()))
+    .with_packed(Some(PackedPeer::from(chat.pack())))
+}
+
+/// Resolved peer recorded for the chat an invite link previews, when the account can see it.
+fn invite_peer(chat: tl::enums::Chat) -> Option<ResolvedPeer> {
+    let (id, access_hash, kind, title, username) = match chat {
+        tl::enums::Chat::Chat(chat) => (chat.id, None, PackedPeerKind::Chat, chat.title, None),
+        tl::enums::Chat::Channel(channel) => (
+            channel.id,
+            channel.access_hash,
+            if channel.megagroup {
+                PackedPeerKind::Megagroup
+            } else if channel.gigagroup {
+                PackedPeerKind::Gigagroup
+            } else {
+                PackedPeerKind::Broadcast
+            },
+            channel.title,
+            channel.username,
+        ),
+        _ => return None,
+    };
+
+    Some(
+        ResolvedPeer::new(id.to_string(), username.unwrap_or_else(|| id.to_string()))
+            .with_name(Some(title))
+            .with_packed(Some(PackedPeer {
+                id,
+                access_hash,
+                kind,
+            })),
+    )
+}
+
+/// Signs in with a phone number and login code, and the 2FA password when the account has one.
+async fn sign_in_interactively(client: &Client) -> Result<User> {
+    let phone = Text::new("Enter Phone (INTL): ").prompt()?;
+    let token = client.request_login_code(&phone).await?;
+    let code = Text::new("Enter Code Sent: ").prompt()?;
+
+    match client.sign_in(&token, &code).await {
+        Ok(user) => Ok(user),
+        Err(SignInError::PasswordRequired(ptoken)) => {
+            let pass = Password::new(&format!(
+                "Please enter 2FA password [Hint: {}]: ",
+                &ptoken.hint().unwrap_or_default()
+            ))
+            .without_confirmation()
+            .with_display_mode(PasswordDisplayMode::Masked)
+            .prompt()?;
+
+            Ok(client.check_password(ptoken, pass).await?)
+        }
+        Err(e) => Err(eyre!(e)),
+    }
+}
+
+async fn wait_out_flood(username: &str, seconds: Option<u32>, progress: &Option<ProgressBar>) {
+    let time = match seconds {
+        Some(val) => val.to_string(),
+        None => "DEFAULT_30".to_string(),
+    };
+
+    if let Some(prog) = progress {
+        prog.set_message(format!("{} - Flood Wait For {} Seconds", username, &time))
+    };
+
+    sleep(Duration::from_secs(seconds.unwrap_or(30) as u64)).await;
+
+    if let Some(prog) = progress {
+        prog.set_message(username.to_string())
+    };
+}
 
 #[derive(Debug)]
 pub struct TelegramClient(pub Client);
 
 impl TelegramClient {
-    pub async fn init() -> Result<Self> {
-        let env = Environment::read()?;
-
-        let client = Client::connect(Config {
+    async fn connect(env: &Environment) -> Result<Client> {
+        Ok(Client::connect(Config {
             session: Session::load_file_or_create(env.telegram.session_path.clone())?,
-            api_id: env.telegram.api_id.clone() as i32,
+            api_id: env.telegram.api_id as i32,
             api_hash: env.telegram.api_hash.clone(),
-            params: InitParams::default(),
+            params: InitParams,
         })
-        .await?;
+        .await?)
+    }
+
+    /// Connects with the stored session. A session that is not logged in is signed in as a bot when
+    /// `TELEGRAM_BOT_TOKEN` is set, otherwise through the login prompts, or fails when `prompt` is off.
+    pub async fn init(prompt: bool) -> Result<Self> {
+        let env = Environment::read()?;
+        let client = Self::connect(&env).await?;
 
         if !client.is_authorized().await? {
-            let phone = Text::new("Enter Phone (INTL): ").prompt()?;
-            let token = client.request_login_code(&phone).await?;
-            let code = Text::new("Enter Code Sent: ").prompt()?;
-
-            match client.sign_in(&token, &code).await {
-                Ok(_) => {}
-                Err(e) => match e {
-                    grammers_client::SignInError::PasswordRequired(ptoken) => {
-                        let pass = Text::new(&format!(
-                            "Please enter 2FA password [Hint: {}] [ECHO]: ",
-                            &ptoken.hint().unwrap_or_default()
-                        ))
-                        .prompt()?;
-                        client.check_password(ptoken, pass).await?;
-                    }
-                    e => {
-                        eprintln!("Unknown Sign In Error: {}", e);
-                        client.sign_out().await?;
-                    }
-                },
+            if let Some(token) = &env.telegram.bot_token {
+                client.bot_sign_in(token).await?;
+            } else if prompt {
+                sign_in_interactively(&client).await?;
+            } else {
+                return Err(eyre!(TelegramAuthorizationError::new(
+                    env.telegram.session_path.clone(),
+                    "Not logged in and prompts are disabled, run `data telegram login` first"
+                        .to_string()
+                )));
             }
 
             client
@@ -59,6 +596,44 @@ impl TelegramClient {
         Ok(TelegramClient(client))
     }
 
+    /// Creates the session file for later runs, as a bot when `bot` is set. An already logged in
+    /// session is left as is.
+    pub async fn login(bot: bool) -> Result<()> {
+        let env = Environment::read()?;
+        let client = Self::connect(&env).await?;
+
+        let user = if client.is_authorized().await? {
+            client.get_me().await?
+        } else {
+            let user = if bot {
+                let token = match &env.telegram.bot_token {
+                    Some(token) => token.clone(),
+                    None => Password::new("Enter Bot Token: ")
+                        .without_confirmation()
+                        .with_display_mode(PasswordDisplayMode::Masked)
+                        .prompt()?,
+                };
+                client.bot_sign_in(&token).await?
+            } else {
+                sign_in_interactively(&client).await?
+            };
+
+            client
+                .session()
+                .save_to_file(env.telegram.session_path.clone())?;
+            user
+        };
+
+        println!(
+            "Logged in as {} ({}), session saved to {}",
+            user.full_name(),
+            user.username().unwrap_or_default(),
+            env.telegram.session_path
+        );
+
+        Ok(())
+    }
+
     pub async fn resolve_username(
         &self,
         username: String,
@@ -100,87 +675,440 @@ impl TelegramClient {
         Ok(chat)
     }
 
-    pub async fn dump_username(
+    /// Every dialog of the session, archived ones included.
+    pub async fn load_dialogs(&self) -> Result<Vec<Dialog>> {
+        let mut iter = self.0.iter_dialogs();
+        let mut dialogs: Vec<Dialog> = vec![];
+
+        loop {
+            match iter.next().await {
+                Ok(Some(dialog)) => dialogs.push(dialog),
+                Ok(None) => break,
+                Err(InvocationError::Rpc(rpc_error)) if rpc_error.code == 420 => {
+                    wait_out_flood("dialogs", rpc_error.value, &None).await;
+                }
+                Err(e) => return Err(eyre!(e)),
+            }
+        }
+
+        Ok(dialogs)
+    }
+
+    /// Resolves a line of the targets file, looking ids and post links up in `dialogs`.
+    pub async fn resolve_target(
         &self,
-        username: String,
-        limit: Option<u32>,
-        date_point: DateTime<Utc>,
-        reverse: bool,
+        input: &str,
+        target: &TelegramTarget,
+        dialogs: &[Chat],
         progress: Option<ProgressBar>,
-    ) -> Result<Vec<PeerMessage>> {
-        let user = self.resolve_username(username.clone(), None).await?;
+    ) -> Result<ResolvedPeer> {
+        let resolved = match target {
+            TelegramTarget::Username(username, _) => {
+                return Ok(
+                    chat_peer(&self.resolve_username(username.clone(), progress).await?)
+                        .with_input(Some(PeerInput::new(target.form(), input.to_string()))),
+                );
+            }
+            TelegramTarget::PostLink(id) | TelegramTarget::PeerId(id) => dialogs
+                .iter()
+                .find(|chat| chat.id() == *id)
+                .map(chat_peer)
+                .ok_or_else(|| "Not found among the session's dialogs".to_string()),
+            TelegramTarget::Invite(hash) => {
+                match self
+                    .0
+                    .invoke(&tl::functions::messages::CheckChatInvite { hash: hash.clone() })
+                    .await
+                {
+                    Ok(tl::enums::ChatInvite::Already(invite)) => invite_peer(invite.chat)
+                        .ok_or_else(|| "The invite's chat is no longer accessible".to_string()),
+                    Ok(tl::enums::ChatInvite::Peek(invite)) => invite_peer(invite.chat)
+                        .ok_or_else(|| "The invite's chat is no longer accessible".to_string()),
+                    Ok(tl::enums::ChatInvite::Invite(invite)) => Err(format!(
+                        "Valid invite to '{}', but the account has not joined it",
+                        invite.title
+                    )),
+                    Err(e) => Err(format!("Unable to check invite ({})", e)),
+                }
+            }
+        };
 
-        let mut messages: Vec<PeerMessage> = vec![];
+        match resolved {
+            Ok(peer) => {
+                if let Some(ref prog) = progress {
+                    prog.inc(1);
+                    prog.finish_with_message(format!("{} - Resolved", input));
+                }
 
-        let date_point_as_utimestamp = date_point.timestamp();
+                Ok(peer.with_input(Some(PeerInput::new(target.form(), input.to_string()))))
+            }
+            Err(message) => {
+                if let Some(ref prog) = progress {
+                    prog.finish_with_message(format!("{} - Failed", input));
+                }
+
+                Err(eyre!(UsernameResolveError::new(input.to_string(), message)))
+            }
+        }
+    }
+
+    /// Finds `peer` again for a fresh access hash, by its public username when it has one and among
+    /// the session's dialogs otherwise.
+    async fn refind_peer(&self, peer: &ResolvedPeer) -> Result<PackedChat> {
+        if let Some(username) = public_username(peer) {
+            return Ok(self
+                .resolve_username(username.to_string(), None)
+                .await?
+                .pack());
+        }
 
-        let mut chunks = match limit {
-            Some(lim) => self.0.iter_messages(&user).limit(lim as usize),
-            None => self.0.iter_messages(&user),
+        let id = peer.peer_id.parse::<i64>().ok();
+        self.load_dialogs()
+            .await?
+            .iter()
+            .map(|dialog| dialog.chat())
+            .find(|chat| Some(chat.id()) == id)
+            .map(|chat| chat.pack())
+            .ok_or_else(|| eyre!(StalePeerError::new(peer.peer_id.clone())))
+    }
+
+    /// Dumps a resolved peer through its stored packed chat, finding it again only when none was
+    /// stored or Telegram no longer accepts its access hash.
+    pub async fn dump_peer(
+        &self,
+        peer: &ResolvedPeer,
+        options: DumpOptions,
+        sink: &mut PeerSink,
+        progress: Option<ProgressBar>,
+    ) -> Result<()> {
+        let username = peer.peer_username.clone();
+
+        let chat = match peer.packed {
+            Some(packed) => PackedChat::from(packed),
+            None => self.refind_peer(peer).await?,
         };
 
+        sink.begin(options.resume).await?;
+
+        if let Some(ref prog) = progress {
+            prog.inc(sink.fetched() as u64);
+        }
+
+        let mut dumped = self
+            .dump_chat(chat, &username, &options, sink, &progress)
+            .await;
+
+        // Both directions continue from the sink's cursor, so a retry picks up wherever the stale hash failed
+        if peer.packed.is_some()
+            && let Err(ref e) = dumped
+            && is_stale_peer(e)
+        {
+            let chat = self.refind_peer(peer).await?;
+            dumped = self
+                .dump_chat(chat, &username, &options, sink, &progress)
+                .await;
+        }
+
+        if let Err(e) = dumped {
+            if let Some(ref prog) = progress {
+                prog.finish_and_clear()
+            };
+            return Err(e);
+        }
+
+        if let Some(ref prog) = progress {
+            prog.finish_with_message(format!("{} - Dumped", &username));
+        }
+
+        Ok(())
+    }
+
+    async fn dump_chat(
+        &self,
+        chat: PackedChat,
+        username: &str,
+        options: &DumpOptions,
+        sink: &mut PeerSink,
+        progress: &Option<ProgressBar>,
+    ) -> Result<()> {
+        if options.reverse {
+            self.dump_forward(chat, username, options, sink, progress)
+                .await
+        } else {
+            self.dump_backward(chat, username, options, sink, progress)
+                .await
+        }
+    }
+
+    /// Walks backwards from `until` (or the newest message) until `date_point` is reached.
+    async fn dump_backward(
+        &self,
+        chat: PackedChat,
+        username: &str,
+        options: &DumpOptions,
+        sink: &mut PeerSink,
+        progress: &Option<ProgressBar>,
+    ) -> Result<()> {
+        let date_point_as_utimestamp = options.date_point.timestamp();
+
+        let mut chunks = self.0.iter_messages(chat);
+
+        if let Some(lim) = options.limit {
+            let remaining = (lim as usize).saturating_sub(sink.fetched());
+            if remaining == 0usize {
+                return Ok(());
+            }
+            chunks = chunks.limit(remaining);
+        }
+
+        // Seek straight to the resume point or the upper bound rather than paging down to it from the newest message
+        if let Some(cursor) = sink.cursor() {
+            chunks = chunks.offset_id(cursor.parse::<i32>()?);
+        } else if let Some(until) = options.until {
+            chunks = chunks.max_date(until.timestamp().saturating_add(1) as i32);
+        }
+
+        let mut pending: Vec<PeerMessage> = vec![];
+
         'message_loop: loop {
             let fetch = chunks.next().await;
             match fetch {
                 Ok(Some(msg)) => {
                     let message: Message = msg;
 
-                    let id = message.id().to_string();
-                    let user_id = match message.sender() {
-                        Some(sender) => format!("user{}", sender.id()).to_string(),
-                        None => "userXXX".to_string(),
-                    };
-                    let content = message.text().to_string();
-                    let date = message.date();
+                    if message.date().timestamp() < date_point_as_utimestamp {
+                        break 'message_loop;
+                    }
 
-                    messages.push(PeerMessage::new(id, user_id, content, date.clone()));
-                    if let Some(ref prog) = progress {
+                    pending.push(collect_message(&message, sink).await);
+                    if let Some(prog) = progress {
                         prog.inc(1);
                     }
 
-                    if date.timestamp() <= date_point_as_utimestamp {
-                        break 'message_loop;
+                    if pending.len() >= HISTORY_PAGE_SIZE {
+                        sink.write_page(std::mem::take(&mut pending)).await?;
                     }
                 }
                 Ok(None) => {
                     break 'message_loop;
                 }
-                Err(e) => match e {
-                    grammers_client::InvocationError::Rpc(rpc_error) => {
-                        if rpc_error.code == 420 {
-                            let time = match rpc_error.value {
-                                Some(val) => val.to_string(),
-                                None => "DEFAULT_30".to_string(),
-                            };
-
-                            if let Some(ref prog) = progress {
-                                prog.set_message(format!(
-                                    "{} - Flood Wait For {} Seconds",
-                                    &username, &time
-                                ))
-                            };
-
-                            sleep(Duration::from_secs(rpc_error.value.unwrap_or(30) as u64)).await;
-
-                            if let Some(ref prog) = progress {
-                                prog.set_message(username.clone())
-                            };
-                        }
-                    }
-                    e => {
-                        if let Some(ref prog) = progress {
-                            prog.finish_and_clear()
-                        };
-                        return Err(eyre!(e));
-                    }
+                Err(InvocationError::Rpc(rpc_error)) if rpc_error.code == 420 => {
+                    wait_out_flood(username, rpc_error.value, progress).await;
+                }
+                Err(e) => {
+                    return Err(eyre!(e));
+                }
+            }
+        }
+
+        sink.write_page(pending).await
+    }
+
+    /// Walks forward from `date_point` to `until` (or the present), oldest message first.
+    async fn dump_forward(
+        &self,
+        chat: PackedChat,
+        username: &str,
+        options: &DumpOptions,
+        sink: &mut PeerSink,
+        progress: &Option<ProgressBar>,
+    ) -> Result<()> {
+        let input_peer = chat.to_input_peer();
+        let mut cursor_id = match sink.cursor() {
+            Some(cursor) => cursor.parse::<i32>()?,
+            None => 0i32,
+        };
+
+        'page_loop: loop {
+            let page_size = match options.limit {
+                Some(lim) if sink.fetched() >= lim as usize => break 'page_loop,
+                Some(lim) => (lim as usize - sink.fetched()).min(HISTORY_PAGE_SIZE),
+                None => HISTORY_PAGE_SIZE,
+            } as i32;
+
+            // A negative add_offset flips the window to the messages right after the offset instead of before it.
+            // The first page is anchored on the date, every following one on the last id already collected.
+            let request = tl::functions::messages::GetHistory {
+                peer: input_peer.clone(),
+                offset_id: if cursor_id > 0 { cursor_id + 1 } else { 0 },
+                offset_date: if cursor_id > 0 {
+                    0
+                } else {
+                    options.date_point.timestamp() as i32
                 },
+                add_offset: -page_size,
+                limit: page_size,
+                max_id: 0,
+                min_id: cursor_id,
+                hash: 0,
+            };
+
+            let history = match self.0.invoke(&request).await {
+                Ok(history) => history,
+                Err(InvocationError::Rpc(rpc_error)) if rpc_error.code == 420 => {
+                    wait_out_flood(username, rpc_error.value, progress).await;
+                    continue 'page_loop;
+                }
+                Err(e) => return Err(eyre!(e)),
+            };
+
+            let (raw_messages, users, chats) = match history {
+                tl::enums::messages::Messages::Messages(m) => (m.messages, m.users, m.chats),
+                tl::enums::messages::Messages::Slice(m) => (m.messages, m.users, m.chats),
+                tl::enums::messages::Messages::ChannelMessages(m) => (m.messages, m.users, m.chats),
+                tl::enums::messages::Messages::NotModified(_) => (vec![], vec![], vec![]),
+            };
+
+            // The page comes back newest first, convert it in place with the users and chats it carries
+            let chat_map = ChatMap::new(users, chats);
+            let mut page = raw_messages
+                .into_iter()
+                .filter_map(|raw| Message::from_raw(&self.0, raw, &chat_map))
+                .filter(|message| message.id() > cursor_id)
+                .collect::<Vec<Message>>();
+
+            if page.is_empty() {
+                break 'page_loop;
             }
+
+            page.sort_unstable_by_key(|message| message.id());
+            let last_id = page[page.len() - 1usize].id();
+
+            let mut fetched: Vec<PeerMessage> = vec![];
+            let mut reached_until = false;
+
+            for message in page {
+                if let Some(until) = options.until
+                    && message.date() > until
+                {
+                    reached_until = true;
+                    break;
+                }
+
+                fetched.push(collect_message(&message, sink).await);
+                if let Some(prog) = progress {
+                    prog.inc(1);
+                }
+            }
+
+            sink.write_page(fetched).await?;
+
+            if reached_until {
+                break 'page_loop;
+            }
+
+            cursor_id = last_id;
         }
 
-        if let Some(ref prog) = progress {
-            prog.finish_with_message(format!("{} - Dumped", &username));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(input: &str) -> TelegramTarget {
+        input.parse::<TelegramTarget>().unwrap()
+    }
+
+    #[test]
+    fn usernames_are_read_with_or_without_at() {
+        for input in ["durov", "@durov", "  @durov\t"] {
+            assert_eq!(
+                target(input),
+                TelegramTarget::Username("durov".to_string(), PeerInputForm::Username)
+            );
+        }
+        assert_eq!(target("@durov").form(), PeerInputForm::Username);
+    }
+
+    #[test]
+    fn public_links_name_the_username() {
+        for input in [
+            "https://t.me/durov",
+            "t.me/durov/42",
+            "http://www.telegram.me/durov?start=1",
+            "https://telegram.dog/durov#top",
+            "https://t.me/s/durov",
+            "t.me/s/durov/42",
+        ] {
+            assert_eq!(
+                target(input),
+                TelegramTarget::Username("durov".to_string(), PeerInputForm::PublicLink),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(target("t.me/s/durov").form(), PeerInputForm::PublicLink);
+    }
+
+    #[test]
+    fn post_links_point_into_private_channels() {
+        assert_eq!(
+            target("https://t.me/c/1234567890/15"),
+            TelegramTarget::PostLink(1234567890)
+        );
+        assert_eq!(target("t.me/c/1234567890").form(), PeerInputForm::PostLink);
+        assert!(target("t.me/c/1234567890/15").needs_dialogs());
+        assert!(
+            "https://t.me/c/channel/15"
+                .parse::<TelegramTarget>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn invite_links_keep_their_hash() {
+        for input in [
+            "https://t.me/+AbCdEf123",
+            "t.me/joinchat/AbCdEf123",
+            "https://telegram.me/joinchat/AbCdEf123/",
+        ] {
+            assert_eq!(
+                target(input),
+                TelegramTarget::Invite("AbCdEf123".to_string()),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(target("t.me/+AbCdEf123").form(), PeerInputForm::InviteLink);
+        assert!(!target("t.me/+AbCdEf123").needs_dialogs());
+    }
+
+    #[test]
+    fn ids_drop_bot_api_prefixes() {
+        assert_eq!(target("-1001234567890"), TelegramTarget::PeerId(1234567890));
+        assert_eq!(target("-123456"), TelegramTarget::PeerId(123456));
+        assert_eq!(target("777000"), TelegramTarget::PeerId(777000));
+        assert_eq!(target("-1001234567890").form(), PeerInputForm::PeerId);
+        assert!(target("777000").needs_dialogs());
+    }
+
+    #[test]
+    fn links_without_a_chat_are_refused() {
+        for input in [
+            "https://t.me/",
+            "t.me/?start=1",
+            "t.me/c",
+            "https://t.me/c/",
+            "t.me/joinchat",
+            "t.me/joinchat/",
+            "t.me/s",
+            "t.me/+",
+        ] {
+            assert!(input.parse::<TelegramTarget>().is_err(), "{}", input);
         }
+    }
 
-        Ok(messages)
+    #[test]
+    fn phone_number_links_are_not_invites() {
+        for input in ["t.me/+972501234567", "https://t.me/+15551234567"] {
+            assert!(input.parse::<TelegramTarget>().is_err(), "{}", input);
+        }
+        assert_eq!(
+            target("t.me/+1AbC"),
+            TelegramTarget::Invite("1AbC".to_string())
+        );
     }
 }
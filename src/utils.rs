@@ -1,34 +1,115 @@
-use color_eyre::eyre::{Result, eyre};
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Report, Result, eyre};
 use encoding_rs::UTF_8;
-use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
-use std::{path::PathBuf, str::FromStr};
-use tokio::fs::{read, try_exists};
+use reqwest::header::{AUTHORIZATION, HeaderMap, HeaderName, HeaderValue};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Value, from_str, to_string};
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tokio::{
+    fs::{OpenOptions, read, try_exists},
+    io::AsyncWriteExt,
+};
 
 use crate::{
     env::Environment,
-    error::{DecodingError, FileExtensionError, FileNotFoundError},
+    error::{
+        DateTimeParseError, DecodingError, DeserializationError, DiscordAPIError,
+        FileExtensionError, FileNotFoundError, MissingAuthorizationError, UsernameResolveError,
+    },
 };
 
+/// Renders a timestamp the way it appears in output filenames.
+pub fn file_timestamp(date: &DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        .replace("+", "_")
+        .replace(":", "_")
+}
+
+/// Parses sizes given on the command line: plain bytes or a number with a K, M or G suffix (powers of 1024).
+pub fn parse_byte_size(size: &str) -> std::result::Result<u64, String> {
+    let size = size.trim();
+    let (number, multiplier) = match size.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&size[..size.len() - 1], 1024u64),
+        Some('M') => (&size[..size.len() - 1], 1024u64.pow(2)),
+        Some('G') => (&size[..size.len() - 1], 1024u64.pow(3)),
+        _ => (size, 1u64),
+    };
+
+    number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|n| n.is_finite() && *n >= 0f64)
+        .map(|n| (n * multiplier as f64) as u64)
+        .ok_or_else(|| format!("invalid size '{}', expected e.g. 500K, 20M or 2G", size))
+}
+
+/// Single line, uncolored explanation of an error, for reports written to disk.
+pub fn error_reason(error: &Report) -> String {
+    if let Some(error) = error.downcast_ref::<UsernameResolveError>() {
+        return error.message.clone();
+    }
+    if let Some(error) = error.downcast_ref::<DiscordAPIError>() {
+        return format!(
+            "{} answered {}: {}",
+            error.resource,
+            error.status,
+            error.reason()
+        );
+    }
+
+    let mut reason = String::new();
+    let text = error.to_string();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            // ANSI color sequences run up to their closing `m`
+            '\x1b' => {
+                for c in chars.by_ref() {
+                    if c == 'm' {
+                        break;
+                    }
+                }
+            }
+            '\n' => reason.push(' '),
+            c => reason.push(c),
+        }
+    }
+
+    reason.trim().to_string()
+}
+
+pub fn timestamp_to_datetime(timestamp: i64) -> Result<DateTime<Utc>> {
+    match DateTime::from_timestamp_secs(timestamp) {
+        Some(date) => Ok(date),
+        None => Err(eyre!(DateTimeParseError::new(
+            timestamp.to_string(),
+            "NO DATE_POINT FOUND AFTER PARSE".to_string()
+        ))),
+    }
+}
+
 pub async fn file_exists(filepath: &PathBuf) -> Result<()> {
     match try_exists(filepath).await {
         Ok(exists) => {
             if exists {
-                return Ok(());
+                Ok(())
             } else {
-                return Err(eyre!(FileNotFoundError::new(
+                Err(eyre!(FileNotFoundError::new(
                     filepath.to_string_lossy().to_string()
-                )));
+                )))
             }
         }
-        Err(e) => {
-            return Err(eyre!(
-                format!("UNKNOWN ERROR OCCURRED: {:#?}", e).to_string()
-            ));
-        }
+        Err(e) => Err(eyre!(
+            format!("UNKNOWN ERROR OCCURRED: {:#?}", e).to_string()
+        )),
     }
 }
 
-pub async fn file_extension_matches(filepath: &PathBuf, expected_extension: &str) -> Result<()> {
+pub async fn file_extension_matches(filepath: &Path, expected_extension: &str) -> Result<()> {
     let actual_extension = filepath.extension();
 
     let found_extension_lossy = actual_extension
@@ -44,10 +125,333 @@ pub async fn file_extension_matches(filepath: &PathBuf, expected_extension: &str
     }
 }
 
+/// Appends every item as its own JSON line, creating the file when missing.
+pub async fn append_json_lines<T: Serialize>(filepath: &PathBuf, items: &[T]) -> Result<()> {
+    let mut buf = String::new();
+    for item in items.iter() {
+        buf.push_str(&to_string(item)?);
+        buf.push('\n');
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(filepath)
+        .await?;
+    file.write_all(buf.as_bytes()).await?;
+    file.flush().await?;
+
+    Ok(())
+}
+
+/// Reads a JSON lines file, a torn trailing line left by an interrupted write is dropped.
+pub async fn read_json_lines<T: DeserializeOwned>(filepath: &PathBuf) -> Result<Vec<T>> {
+    let buf = read(filepath).await?;
+    let (text, _, error) = UTF_8.decode(&buf);
+
+    if error {
+        return Err(eyre!(DecodingError::new(
+            filepath.to_string_lossy().to_string()
+        )));
+    }
+
+    let lines = text
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .collect::<Vec<&str>>();
+
+    let mut items = Vec::with_capacity(lines.len());
+    for (index, line) in lines.iter().enumerate() {
+        match from_str::<T>(line) {
+            Ok(item) => items.push(item),
+            Err(_) if index == lines.len() - 1usize => break,
+            Err(e) => {
+                return Err(eyre!(DeserializationError::new(
+                    filepath.to_string_lossy().to_string(),
+                    e.to_string()
+                )));
+            }
+        }
+    }
+
+    Ok(items)
+}
+
+/// Shapes of the credentials file `AUTH_FILE` points at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CredentialFormat {
+    /// The `Invoke-WebRequest` snippet from the browser's "Copy as PowerShell", or loose `key=value` lines
+    PowerShell,
+    /// A TOML table of headers, at the top level or under `[headers]`
+    Toml,
+    /// "Copy as cURL (bash)"
+    Curl,
+    /// A HAR export, or a JSON object of headers
+    Json,
+    /// The token alone
+    Token,
+}
+
+impl CredentialFormat {
+    fn detect(text: &str) -> Self {
+        let text = text.trim();
+
+        if text.starts_with('{') {
+            CredentialFormat::Json
+        } else if text.starts_with("curl ") {
+            CredentialFormat::Curl
+        } else if text.contains("Invoke-WebRequest") || text.contains("$session") {
+            CredentialFormat::PowerShell
+        } else if text.lines().filter(|l| !l.trim().is_empty()).count() == 1usize
+            && !text.contains('=')
+        {
+            CredentialFormat::Token
+        } else if toml::from_str::<toml::Table>(text).is_ok() {
+            CredentialFormat::Toml
+        } else {
+            CredentialFormat::PowerShell
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            CredentialFormat::PowerShell => "PowerShell headers",
+            CredentialFormat::Toml => "TOML headers",
+            CredentialFormat::Curl => "cURL command",
+            CredentialFormat::Json => "HAR/JSON headers",
+            CredentialFormat::Token => "plain token",
+        }
+    }
+}
+
+/// `key=value` lines, quoted or not, as found in the headers block of the PowerShell snippet.
+fn key_value_headers(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| {
+            !line.is_empty()
+                && !line.starts_with('#')
+                && !line.starts_with('[')
+                && !line.contains("$session")
+                && !line.contains("Invoke-WebRequest")
+                && line.contains("=")
+        })
+        .map(|line| {
+            let mut parts = line.splitn(2, "=");
+            let key = parts.next().unwrap().trim().trim_matches('"');
+            let value = parts
+                .next()
+                .unwrap()
+                .trim()
+                .trim_matches('"')
+                .trim_matches('`');
+            (key.to_string(), value.to_string())
+        })
+        .collect()
+}
+
+/// String values of a TOML document's `headers` table, or of its top level when it has none.
+fn toml_headers(text: &str, filepath: &str) -> Result<Vec<(String, String)>> {
+    let table = toml::from_str::<toml::Table>(text).map_err(|e| {
+        eyre!(DeserializationError::new(
+            filepath.to_string(),
+            e.to_string()
+        ))
+    })?;
+
+    let map = match table.get("headers") {
+        Some(headers) => headers.as_table(),
+        None => Some(&table),
+    };
+
+    Ok(map
+        .map(|map| {
+            map.iter()
+                .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/// Splits a shell command into words the way bash would for the quoting browsers emit: single
+/// quotes, `$'...'` with backslash escapes, double quotes and backslash line continuations.
+fn shell_words(command: &str) -> Vec<String> {
+    let mut words: Vec<String> = vec![];
+    let mut word: Option<String> = None;
+    let mut chars = command.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(word) = word.take() {
+                    words.push(word);
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') | Some('\r') | None => {}
+                Some(escaped) => word.get_or_insert_default().push(escaped),
+            },
+            '\'' => {
+                let word = word.get_or_insert_default();
+                for quoted in chars.by_ref() {
+                    if quoted == '\'' {
+                        break;
+                    }
+                    word.push(quoted);
+                }
+            }
+            '$' if chars.peek() == Some(&'\'') => {
+                chars.next();
+                let word = word.get_or_insert_default();
+                while let Some(quoted) = chars.next() {
+                    match quoted {
+                        '\'' => break,
+                        '\\' => match chars.next() {
+                            Some('n') => word.push('\n'),
+                            Some('t') => word.push('\t'),
+                            Some('r') => word.push('\r'),
+                            Some(escaped) => word.push(escaped),
+                            None => break,
+                        },
+                        quoted => word.push(quoted),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_default();
+                while let Some(quoted) = chars.next() {
+                    match quoted {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some(escaped @ ('"' | '\\' | '$' | '`')) => word.push(escaped),
+                            Some('\n') => {}
+                            Some(escaped) => {
+                                word.push('\\');
+                                word.push(escaped);
+                            }
+                            None => break,
+                        },
+                        quoted => word.push(quoted),
+                    }
+                }
+            }
+            c => word.get_or_insert_default().push(c),
+        }
+    }
+
+    if let Some(word) = word {
+        words.push(word);
+    }
+
+    words
+}
+
+/// Headers passed to a cURL command with `-H`, plus its cookies and user agent.
+fn curl_headers(command: &str) -> Vec<(String, String)> {
+    let words = shell_words(command);
+    let mut headers: Vec<(String, String)> = vec![];
+
+    let mut words = words.iter();
+    while let Some(word) = words.next() {
+        match word.as_str() {
+            "-H" | "--header" => {
+                if let Some((key, value)) = words.next().and_then(|h| h.split_once(':')) {
+                    headers.push((key.trim().to_string(), value.trim().to_string()));
+                }
+            }
+            "-b" | "--cookie" => {
+                if let Some(cookie) = words.next() {
+                    headers.push(("cookie".to_string(), cookie.to_string()));
+                }
+            }
+            "-A" | "--user-agent" => {
+                if let Some(agent) = words.next() {
+                    headers.push(("user-agent".to_string(), agent.to_string()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    headers
+}
+
+/// Request headers from a HAR export or a plain JSON object of headers (optionally under `headers`).
+/// Of a HAR's requests, the first to carry an Authorization header is used, preferring API calls.
+fn json_headers(text: &str, filepath: &str) -> Result<Vec<(String, String)>> {
+    let value = from_str::<Value>(text).map_err(|e| {
+        eyre!(DeserializationError::new(
+            filepath.to_string(),
+            e.to_string()
+        ))
+    })?;
+
+    if let Some(entries) = value.pointer("/log/entries").and_then(|e| e.as_array()) {
+        let request_headers = |entry: &Value| -> Vec<(String, String)> {
+            entry
+                .pointer("/request/headers")
+                .and_then(|h| h.as_array())
+                .map(|headers| {
+                    headers
+                        .iter()
+                        .filter_map(|h| {
+                            Some((
+                                h.get("name")?.as_str()?.to_string(),
+                                h.get("value")?.as_str()?.to_string(),
+                            ))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let is_api_call = |entry: &Value| {
+            entry
+                .pointer("/request/url")
+                .and_then(|u| u.as_str())
+                .is_some_and(|url| url.contains("/api/"))
+        };
+        let authorized = |entry: &&Value| {
+            request_headers(entry)
+                .iter()
+                .any(|(key, _)| key.eq_ignore_ascii_case("authorization"))
+        };
+
+        let entry = entries
+            .iter()
+            .filter(authorized)
+            .find(|entry| is_api_call(entry))
+            .or_else(|| entries.iter().find(authorized));
+
+        return Ok(entry.map(request_headers).unwrap_or_default());
+    }
+
+    let map = value.get("headers").unwrap_or(&value);
+
+    Ok(map
+        .as_object()
+        .map(|map| {
+            map.iter()
+                .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/// Reads the headers Discord requests are sent with from `AUTH_FILE`, which can hold a request copied
+/// from the browser (as PowerShell, as cURL or a HAR export), a TOML or JSON map of headers, or the
+/// token alone.
 pub async fn get_discord_headermap() -> Result<HeaderMap> {
     let env = Environment::read()?;
 
-    let skip = ["authority", "method", "path", "scheme"];
+    // HTTP/2 pseudo headers and ones reqwest works out itself
+    let skip = [
+        "authority",
+        "method",
+        "path",
+        "scheme",
+        "content-length",
+        "host",
+    ];
     let mut headers = HeaderMap::new();
 
     let file = {
@@ -62,27 +466,21 @@ pub async fn get_discord_headermap() -> Result<HeaderMap> {
         text.to_string()
     };
 
-    for line in file.lines() {
-        let line = line.trim();
-
-        if line.is_empty()
-            || line.contains("$session")
-            || line.contains("Invoke-WebRequest")
-            || !line.contains("=")
-        {
-            continue;
-        }
+    let format = CredentialFormat::detect(&file);
+    let pairs = match format {
+        CredentialFormat::PowerShell => key_value_headers(&file),
+        CredentialFormat::Toml => toml_headers(&file, &env.discord.auth_file)?,
+        CredentialFormat::Curl => curl_headers(&file),
+        CredentialFormat::Json => json_headers(&file, &env.discord.auth_file)?,
+        CredentialFormat::Token => vec![("authorization".to_string(), file.trim().to_string())],
+    };
 
-        let mut parts = line.splitn(2, "=");
-        let key = parts.next().unwrap().trim().trim_matches('"');
+    for (key, value) in pairs.iter() {
+        let key = key.trim_start_matches(':');
         if skip.iter().any(|&k| k.eq_ignore_ascii_case(key)) {
             continue;
         }
 
-        let raw_value = parts.next().unwrap().trim();
-
-        let value = raw_value.trim_matches('"').trim_matches('`');
-
         headers.insert(
             HeaderName::from_str(key)
                 .map_err(|e| eyre!(format!("Issue with key '{}': {}", key, e)))?,
@@ -90,5 +488,146 @@ pub async fn get_discord_headermap() -> Result<HeaderMap> {
         );
     }
 
+    let authorized = headers
+        .get(AUTHORIZATION)
+        .is_some_and(|value| !value.is_empty());
+    if !authorized {
+        return Err(eyre!(MissingAuthorizationError::new(
+            env.discord.auth_file.clone(),
+            format.name().to_string()
+        )));
+    }
+
     Ok(headers)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn credential_format_is_detected() {
+        let powershell = "$session = New-Object Microsoft.PowerShell.Commands.WebRequestSession\nInvoke-WebRequest -UseBasicParsing -Uri \"https://discord.com/api/v9/users/@me\" `\n-Headers @{\n\"authorization\"=\"abc\"\n}";
+
+        assert_eq!(
+            CredentialFormat::detect(powershell),
+            CredentialFormat::PowerShell
+        );
+        assert_eq!(
+            CredentialFormat::detect(
+                "\"authorization\"=\"abc\" `\n\"x-debug-options\"=\"bugReporterEnabled\" `"
+            ),
+            CredentialFormat::PowerShell
+        );
+        assert_eq!(
+            CredentialFormat::detect("[headers]\nauthorization = 'abc'\n"),
+            CredentialFormat::Toml
+        );
+        assert_eq!(
+            CredentialFormat::detect(
+                "curl 'https://discord.com/api/v9/users/@me' -H 'authorization: abc'"
+            ),
+            CredentialFormat::Curl
+        );
+        assert_eq!(
+            CredentialFormat::detect("  {\"authorization\": \"abc\"}"),
+            CredentialFormat::Json
+        );
+        assert_eq!(
+            CredentialFormat::detect("\nabc.def.ghi\n"),
+            CredentialFormat::Token
+        );
+    }
+
+    #[test]
+    fn toml_headers_follow_toml_quoting() {
+        let text = "[headers]\nauthorization = 'C:\\raw\\token'\n\"user-agent\" = \"Mozilla \\\"5.0\\\"\\tx\"\n";
+
+        assert_eq!(
+            toml_headers(text, "auth.toml").unwrap(),
+            pairs(&[
+                ("authorization", "C:\\raw\\token"),
+                ("user-agent", "Mozilla \"5.0\"\tx")
+            ])
+        );
+    }
+
+    #[test]
+    fn toml_headers_read_inline_and_top_level_tables() {
+        assert_eq!(
+            toml_headers(
+                "headers = { authorization = \"abc\", locale = 'en-US' }",
+                "auth.toml"
+            )
+            .unwrap(),
+            pairs(&[("authorization", "abc"), ("locale", "en-US")])
+        );
+        assert_eq!(
+            toml_headers("authorization = \"abc\"\nretries = 3\n", "auth.toml").unwrap(),
+            pairs(&[("authorization", "abc")])
+        );
+    }
+
+    #[test]
+    fn shell_words_handle_ansi_c_quotes_and_continuations() {
+        let command =
+            "curl $'https://x/\\'a\\'\\tb' \\\n  -H \"a: \\\"b\\\"\" \\\r\n  plain\\ word";
+
+        assert_eq!(
+            shell_words(command),
+            vec!["curl", "https://x/'a'\tb", "-H", "a: \"b\"", "plain word"]
+        );
+    }
+
+    #[test]
+    fn curl_headers_collect_headers_cookies_and_agent() {
+        let command = "curl 'https://discord.com/api/v9/users/@me' \\\n  -H 'authorization: abc' \\\n  -H $'x-super-properties: e30=' \\\n  -b 'locale=en-US; __dcfduid=1' \\\n  -A 'Mozilla/5.0' \\\n  --compressed";
+
+        assert_eq!(
+            curl_headers(command),
+            pairs(&[
+                ("authorization", "abc"),
+                ("x-super-properties", "e30="),
+                ("cookie", "locale=en-US; __dcfduid=1"),
+                ("user-agent", "Mozilla/5.0"),
+            ])
+        );
+    }
+
+    #[test]
+    fn har_prefers_authorized_api_calls() {
+        let har = r#"{"log": {"entries": [
+            {"request": {"url": "https://discord.com/assets/app.js", "headers": [{"name": "accept", "value": "*/*"}]}},
+            {"request": {"url": "https://discord.com/cdn-cgi/rum", "headers": [{"name": "Authorization", "value": "elsewhere"}]}},
+            {"request": {"url": "https://discord.com/api/v9/users/@me", "headers": [
+                {"name": ":authority", "value": "discord.com"},
+                {"name": "Authorization", "value": "abc"}
+            ]}}
+        ]}}"#;
+
+        assert_eq!(
+            json_headers(har, "auth.har").unwrap(),
+            pairs(&[(":authority", "discord.com"), ("Authorization", "abc")])
+        );
+    }
+
+    #[test]
+    fn har_falls_back_to_any_authorized_request() {
+        let har = r#"{"log": {"entries": [
+            {"request": {"url": "https://discord.com/app", "headers": []}},
+            {"request": {"url": "https://discord.com/cdn-cgi/rum", "headers": [{"name": "authorization", "value": "abc"}]}}
+        ]}}"#;
+
+        assert_eq!(
+            json_headers(har, "auth.har").unwrap(),
+            pairs(&[("authorization", "abc")])
+        );
+    }
+}
@@ -11,3 +11,42 @@ pub fn new_multi_progress() -> Result<(MultiProgress, ProgressStyle)> {
 
     Ok((multi_progress, sty))
 }
+
+/// Prints rows as a plain text table, columns padded to their widest cell.
+pub fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths = headers
+        .iter()
+        .map(|h| h.chars().count())
+        .collect::<Vec<usize>>();
+
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let render = |cells: Vec<&str>| {
+        cells
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<String>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    let separators = widths
+        .iter()
+        .map(|w| "-".repeat(*w))
+        .collect::<Vec<String>>();
+
+    println!("{}", render(headers.to_vec()));
+    println!(
+        "{}",
+        render(separators.iter().map(|s| s.as_str()).collect())
+    );
+    for row in rows.iter() {
+        println!("{}", render(row.iter().map(|c| c.as_str()).collect()));
+    }
+}